@@ -0,0 +1,255 @@
+use crate::clicker::ClickerCommand;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Instant;
+
+const HISTOGRAM_BINS: usize = 12;
+const HISTOGRAM_WIDTH: usize = 40;
+const MAX_LAG: usize = 20;
+const REPORTED_LAGS: usize = 5;
+
+// Runs the command's generator on a simulated clock, starting at its activation.
+// Returns the intervals between the clicks that are sent, in milliseconds
+pub fn simulate(cmd: &mut ClickerCommand, clicks: usize) -> Vec<f64> {
+    let mut now = Instant::now();
+    cmd.update(now);
+    cmd.activate(now);
+
+    let mut intervals = Vec::with_capacity(clicks);
+    let mut last_sent: Option<Instant> = None;
+    while intervals.len() < clicks {
+        if cmd.stop_at().is_some_and(|stop| now >= stop) {
+            break;
+        }
+
+        cmd.update(now);
+        let Some(click) = cmd.next_click(now) else {
+            break;
+        };
+
+        if !click.skip {
+            if let Some(last) = last_sent {
+                intervals.push((now - last).as_secs_f64() * 1000.0);
+            }
+            last_sent = Some(now);
+        }
+        now += click.interval;
+    }
+
+    intervals
+}
+
+pub fn print_report(intervals: &[f64]) {
+    if intervals.len() < 2 {
+        println!("Not enough clicks to analyze");
+        return;
+    }
+
+    let total_secs = intervals.iter().sum::<f64>() / 1000.0;
+    let mut cps: Vec<f64> = intervals.iter().map(|ms| 1000.0 / ms).collect();
+    cps.sort_by(f64::total_cmp);
+
+    println!("Clicks: {} over {:.2}s", intervals.len(), total_secs);
+    println!(
+        "CPS: mean {:.2}, median {:.2}, stddev {:.2} (min {:.2}, max {:.2})",
+        intervals.len() as f64 / total_secs,
+        cps[cps.len() / 2],
+        stddev(&cps),
+        cps[0],
+        cps[cps.len() - 1]
+    );
+    println!(
+        "Intervals: mean {:.2}ms, stddev {:.2}ms",
+        mean(intervals),
+        stddev(intervals)
+    );
+
+    println!("Histogram (CPS):");
+    let (min, max) = (cps[0], cps[cps.len() - 1]);
+    let width = ((max - min) / HISTOGRAM_BINS as f64).max(f64::EPSILON);
+    let mut bins = [0usize; HISTOGRAM_BINS];
+    for value in &cps {
+        bins[(((value - min) / width) as usize).min(HISTOGRAM_BINS - 1)] += 1;
+    }
+    let highest = bins.iter().copied().max().unwrap_or(1);
+    for (i, count) in bins.iter().enumerate() {
+        let low = min + width * i as f64;
+        println!(
+            "  {:>6.2} - {:>6.2} | {:<bar$} {}",
+            low,
+            low + width,
+            "#".repeat(count * HISTOGRAM_WIDTH / highest),
+            count,
+            bar = HISTOGRAM_WIDTH
+        );
+    }
+
+    let correlations: Vec<f64> = (1..=MAX_LAG.min(intervals.len() - 1))
+        .map(|lag| autocorrelation(intervals, lag))
+        .collect();
+    let reported: Vec<String> = correlations
+        .iter()
+        .take(REPORTED_LAGS)
+        .enumerate()
+        .map(|(i, r)| format!("lag {}: {:.3}", i + 1, r))
+        .collect();
+    println!("Autocorrelation (intervals): {}", reported.join(", "));
+
+    // The strongest repetition over any lag. Constant or strictly periodic
+    // intervals get close to 1
+    let periodicity = correlations.iter().copied().fold(0.0, f64::max);
+    println!(
+        "Periodicity: {:.3} (highest autocorrelation over lags 1-{}, lower is more human)",
+        periodicity,
+        correlations.len()
+    );
+    println!(
+        "Entropy: {:.2} bits (intervals rounded to 1ms, 0 means every interval is the same)",
+        entropy(intervals)
+    );
+}
+
+pub fn save_csv(intervals: &[f64], path: &str) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "click,interval_ms,cps")?;
+    for (i, ms) in intervals.iter().enumerate() {
+        writeln!(file, "{},{},{}", i + 1, ms, 1000.0 / ms)?;
+    }
+    Ok(())
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn stddev(values: &[f64]) -> f64 {
+    let mean = mean(values);
+    (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt()
+}
+
+fn autocorrelation(values: &[f64], lag: usize) -> f64 {
+    let mean = mean(values);
+    let variance: f64 = values.iter().map(|v| (v - mean).powi(2)).sum();
+    if variance == 0.0 {
+        return 1.0;
+    }
+
+    let covariance: f64 = values
+        .iter()
+        .zip(&values[lag..])
+        .map(|(a, b)| (a - mean) * (b - mean))
+        .sum();
+    covariance / variance
+}
+
+fn entropy(values: &[f64]) -> f64 {
+    let mut counts: HashMap<i64, usize> = HashMap::new();
+    for value in values {
+        *counts.entry(value.round() as i64).or_default() += 1;
+    }
+
+    let n = values.len() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / n;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use rand::Rng;
+
+    const SEEDED: &str = r#"
+        seed = 42
+
+        [[commands]]
+        listen = { type = "Button", value = "B" }
+        action = { type = "Button", value = "L" }
+        method = "Hold"
+        range = { min = 12, max = 18 }
+        hold = { min = 40, max = 90 }
+        artifacts = { miss = 0.05, double = 0.05, pause = 0.05 }
+        fatigue = { rate = 0.005, floor = 0.8, recovery = 0.02, wander = 0.03 }
+        jitter = { amplitude = 1.5, frequency = 20 }
+    "#;
+
+    // 'other_draws' stands in for the jitter and the targets, which draw a
+    // different amount of times depending on when the clicker thread wakes up
+    fn seeded_intervals(other_draws: usize) -> Vec<f64> {
+        let settings = Settings::from_toml(SEEDED).unwrap();
+        let mut cmd = ClickerCommand::parse(&settings, 0).unwrap();
+        for _ in 0..other_draws {
+            cmd.rngs.jitter.gen::<u64>();
+            cmd.rngs.targets.gen::<u64>();
+        }
+        simulate(&mut cmd, 200)
+    }
+
+    #[test]
+    fn same_seed_gives_same_intervals() {
+        let intervals = seeded_intervals(0);
+        assert_eq!(intervals.len(), 200);
+        assert_eq!(intervals, seeded_intervals(0));
+        assert_eq!(intervals, seeded_intervals(37));
+    }
+
+    #[test]
+    fn simulated_intervals_follow_the_range() {
+        let settings = Settings::from_toml(
+            r#"
+            [[commands]]
+            listen = { type = "Button", value = "B" }
+            action = { type = "Button", value = "L" }
+            method = "Hold"
+            range = { min = 10, max = 20 }
+            seed = 1
+            "#,
+        )
+        .unwrap();
+        let mut cmd = ClickerCommand::parse(&settings, 0).unwrap();
+
+        let intervals = simulate(&mut cmd, 500);
+        assert_eq!(intervals.len(), 500);
+        assert!(intervals
+            .iter()
+            .all(|ms| (50.0 - 1e-6..=100.0 + 1e-6).contains(ms)));
+    }
+
+    #[test]
+    fn fatigue_needs_a_positive_floor() {
+        let settings = Settings::from_toml(
+            r#"
+            [[commands]]
+            listen = { type = "Button", value = "B" }
+            action = { type = "Button", value = "L" }
+            method = "Hold"
+            range = { min = 10, max = 20 }
+            fatigue = { rate = 1.0, floor = 0.0, recovery = 0.02 }
+            "#,
+        )
+        .unwrap();
+        assert!(ClickerCommand::parse(&settings, 0).is_none());
+    }
+
+    #[test]
+    fn statistics() {
+        assert_eq!(mean(&[1.0, 2.0, 3.0, 4.0]), 2.5);
+        assert_eq!(stddev(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]), 2.0);
+
+        let alternating: Vec<f64> = (0..100).map(|i| (i % 2) as f64).collect();
+        assert!(autocorrelation(&alternating, 1) < -0.9);
+        assert!(autocorrelation(&alternating, 2) > 0.9);
+        assert_eq!(autocorrelation(&[5.0; 10], 1), 1.0);
+
+        assert_eq!(entropy(&[5.0; 10]), 0.0);
+        assert_eq!(entropy(&[1.0, 2.0, 3.0, 4.0]), 2.0);
+        // Intervals are compared at 1ms
+        assert_eq!(entropy(&[1.1, 0.9, 1.2]), 0.0);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::distribution::RangeSampler;
+use crate::settings;
+use crate::time::millis_to_duration;
+use rand::Rng;
+use std::time::Duration;
+
+// Milliseconds between the two clicks of an accidental double click
+const DEFAULT_DOUBLE_DELAY: (f64, f64) = (25.0, 60.0);
+// Milliseconds added to the interval of a micro-pause
+const DEFAULT_PAUSE_DURATION: (f64, f64) = (150.0, 400.0);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Artifact {
+    // The click is not sent
+    Miss,
+    // Another click follows after the given delay
+    Double(Duration),
+    // The interval to the next click is longer by the given duration
+    Pause(Duration),
+}
+
+// Occasional humanlike mistakes made while clicking
+#[derive(Debug)]
+pub struct Artifacts {
+    miss: f64,
+    double: f64,
+    pause: f64,
+    double_delay: RangeSampler,
+    pause_duration: RangeSampler,
+}
+
+impl Artifacts {
+    pub fn parse(artifacts: &settings::Artifacts) -> Option<Self> {
+        let probabilities = [artifacts.miss, artifacts.double, artifacts.pause];
+        if probabilities.iter().any(|p| !(0.0..=1.0).contains(p))
+            || probabilities.iter().sum::<f64>() > 1.0
+        {
+            return None;
+        }
+
+        Some(Self {
+            miss: artifacts.miss,
+            double: artifacts.double,
+            pause: artifacts.pause,
+            double_delay: parse_delay(artifacts.double_delay.as_ref(), DEFAULT_DOUBLE_DELAY)?,
+            pause_duration: parse_delay(artifacts.pause_duration.as_ref(), DEFAULT_PAUSE_DURATION)?,
+        })
+    }
+
+    // Picks the artifact of a single click, if any
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<Artifact> {
+        let roll: f64 = rng.gen();
+        if roll < self.miss {
+            Some(Artifact::Miss)
+        } else if roll < self.miss + self.double {
+            Some(Artifact::Double(millis_to_duration(
+                self.double_delay.sample(rng),
+            )))
+        } else if roll < self.miss + self.double + self.pause {
+            Some(Artifact::Pause(millis_to_duration(
+                self.pause_duration.sample(rng),
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_delay(range: Option<&settings::MillisRange>, default: (f64, f64)) -> Option<RangeSampler> {
+    let (min, max) = match range {
+        Some(r) => (r.min as f64, r.max as f64),
+        None => default,
+    };
+    RangeSampler::parse(min..=max, None)
+}
@@ -1,103 +1,867 @@
+use crate::artifacts::{Artifact, Artifacts};
+use crate::convert::{string_to_keysym, Keymap};
+use crate::distribution::RangeSampler;
+use crate::fakekeyboard::Key;
+use crate::generator::CpsGenerator;
 use crate::inputsys::InputButton;
-use crate::settings::{InputType, Method, Settings};
-use std::collections::HashMap;
-use std::ops::RangeInclusive;
+use crate::jitter::Jitter;
+use crate::macros::Macro;
+use crate::profile::Profile;
+use crate::ramp::Ramp;
+use crate::settings::{Action, CpsRange, Distribution, Input, InputType, Listen, Method, Settings};
+use crate::targets::Targets;
+use crate::time::{cps_to_interval, millis_to_duration};
+use crate::window::WindowTarget;
+use log::info;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashSet};
+use std::time::{Duration, Instant};
+use x11::xlib;
 
-// TODO: Either have 'is_pressed' or 'next_cps', never both
+// TODO: Either have 'is_pressed' or 'next_action', never both
 #[derive(Debug)]
 pub struct ClickerCommand {
     pub is_active: bool,
     pub is_pressed: bool,
-    pub last_action: i64,
-    pub next_cps: Option<u32>,
+    pub next_action: Option<Instant>,
+    pub release_at: Option<Instant>,
+    pub hold: Option<RangeSampler>,
+    pub rngs: Rngs,
+    pub activated_at: Option<Instant>,
+    pub cooling_since: Option<Instant>,
+    pub warmup: Option<Ramp>,
+    pub cooldown: Option<Ramp>,
+    pub stop_after: Option<Duration>,
+    pub artifacts: Option<Artifacts>,
+    pub jitter: Option<Jitter>,
+    pub targets: Option<Targets>,
+    pub window: Option<WindowTarget>,
+    // The inputs that have to be held together, usually just one
+    pub listen: Vec<ClickerInput>,
+    pub modifiers: BTreeSet<Modifier>,
+    // Whether the chord was completed along with the modifiers and none of its
+    // inputs were released since, no matter the modifiers released meanwhile
+    pub is_triggered: bool,
+    pub device: Option<String>,
+    // Whether the other applications don't get the listened inputs
+    pub consume: bool,
     pub action: ClickerAction,
     pub method: Method,
 }
 
-pub struct ClickerState {
-    pub commands: HashMap<ClickerInput, ClickerCommand>,
-}
+impl ClickerCommand {
+    pub fn parse(settings: &Settings, index: usize) -> Option<Self> {
+        let cmd = settings.commands.get(index)?;
 
-impl ClickerState {
-    pub fn parse(settings: &Settings) -> Option<Self> {
-        let mut clicker_cmds = HashMap::new();
-        for cmd in &settings.commands {
-            let input = match cmd.listen.r#type {
-                InputType::Key => ClickerInput::Key(cmd.listen.value.clone()),
-                InputType::Button => {
-                    ClickerInput::Button(parse_input_button(cmd.listen.value.clone())?)
+        let (listen, modifiers) = parse_listen(&cmd.listen)?;
+
+        // A grabbed input is taken away even when the rest of the chord isn't
+        // held, so only one input besides the modifier keys can be consumed
+        let grabbed = listen.iter().filter(|input| input.modifier().is_none());
+        if cmd.consume && grabbed.count() > 1 {
+            return None;
+        }
+
+        let profile = match &cmd.profile {
+            Some(path) => Some(Profile::load(path)?),
+            None => None,
+        };
+
+        // A recorded profile takes the place of the CPS range
+        let sampler = match (&profile, &cmd.range) {
+            (Some(p), _) => Some(p.cps_sampler()?),
+            (None, Some(r)) => Some(parse_sampler(r, cmd.distribution.as_ref())?),
+            (None, None) => None,
+        };
+        let generator = match sampler {
+            Some(s) => Some(CpsGenerator::parse(
+                s,
+                cmd.model.as_ref(),
+                cmd.fatigue.as_ref(),
+            )?),
+            None => None,
+        };
+
+        let action = match &cmd.action {
+            Action::Macro(m) => {
+                let m = Macro::parse(m)?;
+                // Without any wait, a repeating macro would run in a busy loop
+                if cmd.method != Method::Once && !m.has_wait() {
+                    return None;
                 }
-            };
+                ClickerAction::Macro(m)
+            }
 
-            let action = match cmd.action.r#type {
+            Action::Input(input) => match input.r#type {
                 InputType::Key => {
-                    if let Some(r) = &cmd.range {
-                        let range = r.min..=r.max;
-                        ClickerAction::KeyClick(cmd.action.value.clone(), range)
+                    let key = Key::parse(&input.value)?;
+                    if let Some(generator) = generator {
+                        ClickerAction::KeyClick(key, generator)
                     } else {
-                        ClickerAction::KeyPress(cmd.action.value.clone())
+                        ClickerAction::KeyPress(key)
                     }
                 }
 
                 InputType::Button => {
-                    let button = parse_input_button(cmd.action.value.clone())?;
-                    if let Some(r) = &cmd.range {
-                        let range = r.min..=r.max;
-                        ClickerAction::ButtonClick(button, range)
+                    let button = parse_input_button(input.value.clone())?;
+                    if let Some(generator) = generator {
+                        ClickerAction::ButtonClick(button, generator)
                     } else {
                         ClickerAction::ButtonPress(button)
                     }
                 }
-            };
+            },
+        };
+
+        let hold = match (&cmd.hold, &profile) {
+            (Some(h), _) => Some(RangeSampler::parse(
+                h.min as f64..=h.max as f64,
+                cmd.hold_distribution.as_ref(),
+            )?),
+            (None, Some(p)) => p.hold_sampler(),
+            (None, None) => None,
+        };
+
+        // Every command gets its own generators, so that the sequence of one
+        // command doesn't depend on how often the others click
+        let mut seeder = match (cmd.seed, settings.seed) {
+            (Some(seed), _) => StdRng::seed_from_u64(seed),
+            (None, Some(seed)) => StdRng::seed_from_u64(seed.wrapping_add(index as u64)),
+            (None, None) => StdRng::from_entropy(),
+        };
+        let rngs = Rngs::new(&mut seeder);
+
+        let warmup = match &cmd.warmup {
+            Some(r) => Some(Ramp::parse(r)?),
+            None => None,
+        };
+
+        let cooldown = match &cmd.cooldown {
+            Some(r) => Some(Ramp::parse(r)?),
+            None => None,
+        };
+
+        let artifacts = match &cmd.artifacts {
+            Some(a) => Some(Artifacts::parse(a)?),
+            None => None,
+        };
+
+        // The pointer only jitters for commands using the mouse
+        let jitter = match (&cmd.jitter, &action) {
+            (Some(j), ClickerAction::ButtonClick(..) | ClickerAction::ButtonPress(_)) => {
+                Some(Jitter::parse(j)?)
+            }
+            _ => None,
+        };
+
+        // Only clicks can be aimed, and how the pointer gets there and back
+        // only matters with a position
+        let targets = match (&cmd.position, &action) {
+            (Some(p), ClickerAction::ButtonClick(..)) => {
+                Some(Targets::parse(p, cmd.restore_pointer, cmd.pointer_motion)?)
+            }
+            (Some(_), _) => return None,
+            (None, _) if cmd.restore_pointer || cmd.pointer_motion.is_some() => return None,
+            (None, _) => None,
+        };
+
+        // Only positions can be relative to a window
+        let window = match &cmd.window {
+            Some(w) if targets.is_some() || action.is_macro() => Some(WindowTarget::parse(w)?),
+            Some(_) => return None,
+            None => None,
+        };
+
+        Some(Self {
+            is_active: false,
+            is_pressed: false,
+            next_action: None,
+            release_at: None,
+            hold,
+            rngs,
+            activated_at: None,
+            cooling_since: None,
+            warmup,
+            cooldown,
+            stop_after: cmd.stop_after.map(Duration::from_millis),
+            artifacts,
+            jitter,
+            targets,
+            window,
+            listen,
+            modifiers,
+            is_triggered: false,
+            device: cmd.device.clone(),
+            consume: cmd.consume,
+            action,
+            method: cmd.method.clone(),
+        })
+    }
+
+    pub fn activate(&mut self, now: Instant) {
+        self.is_active = true;
+        self.activated_at = Some(now);
+        self.cooling_since = None;
+        if let Some(j) = &mut self.jitter {
+            j.reset();
+        }
+    }
+
+    pub fn deactivate(&mut self, now: Instant) {
+        self.is_active = false;
+        // Toggled clicks taper off instead of stopping right away
+        if self.method == Method::Toggle && self.cooldown.is_some() && self.action.is_click() {
+            self.cooling_since = Some(now);
+        }
+    }
+
+    // Reacts to the press that completes the chord. Toggle switches right
+    // away, the same way Hold starts
+    pub fn trigger(&mut self, now: Instant) {
+        match self.method {
+            Method::Hold => self.activate(now),
+            Method::Toggle if self.is_active => self.deactivate(now),
+            Method::Toggle => self.activate(now),
+            // A one-shot command can't be restarted while it's still running
+            Method::Once if !self.is_active => self.activate(now),
+            Method::Once => {}
+        }
+    }
+
+    // Reacts to the release of an input of the chord. Toggled and one-shot
+    // commands keep going on their own
+    pub fn untrigger(&mut self, now: Instant) {
+        if self.method == Method::Hold {
+            self.deactivate(now);
+        }
+    }
+
+    // Whether the command should be clicking, which includes the cool-down
+    // after it was toggled off
+    pub fn is_clicking(&self, now: Instant) -> bool {
+        if self.is_active {
+            return true;
+        }
+
+        match (&self.cooldown, self.cooling_since) {
+            (Some(c), Some(since)) => now < since + c.duration,
+            _ => false,
+        }
+    }
+
+    // The instant at which an active command with 'stop_after' stops by itself
+    pub fn stop_at(&self) -> Option<Instant> {
+        if !self.is_active {
+            return None;
+        }
+
+        Some(self.activated_at? + self.stop_after?)
+    }
+
+    // Applies the warm-up and cool-down ramps to the CPS of a click at 'now'
+    pub fn ramp_cps(&self, mut cps: f64, now: Instant) -> f64 {
+        if let (Some(w), Some(activated_at)) = (&self.warmup, self.activated_at) {
+            cps = w.warmup(cps, now.saturating_duration_since(activated_at));
+        }
 
-            // NOTE: The first number doesn't need to be random, since the last
-            // click was at the timestamp '0'. This means that the first click will
-            // always be instantaneous
-            let next_cps = match &cmd.range {
-                Some(r) => Some(r.min),
-                None => None,
+        if let Some(c) = &self.cooldown {
+            // A timed stop is known in advance, so its cool-down ends right
+            // when the command stops
+            let start = match self.cooling_since {
+                Some(since) => Some(since),
+                None => self.stop_at().map(|stop| {
+                    let start = stop.checked_sub(c.duration).unwrap_or(stop);
+                    start.max(self.activated_at.unwrap_or(start))
+                }),
             };
 
-            clicker_cmds.insert(
-                input,
-                ClickerCommand {
-                    is_active: false,
-                    is_pressed: false,
-                    last_action: 0,
-                    next_cps,
-                    action,
-                    method: cmd.method.clone(),
-                },
-            );
+            if let Some(start) = start.filter(|&start| now >= start) {
+                cps = c.cooldown(cps, now - start);
+            }
+        }
+
+        cps
+    }
+
+    // Advances the time based state of the command's generator
+    pub fn update(&mut self, now: Instant) {
+        if let Some(generator) = self.action.generator_mut() {
+            generator.update(self.is_active, now, &mut self.rngs.fatigue);
+        }
+    }
+
+    // Picks the time until the click after the one happening at 'now'
+    pub fn next_interval(&mut self, now: Instant) -> Option<Duration> {
+        let cps = self
+            .action
+            .generator_mut()?
+            .next_cps(&mut self.rngs.intervals);
+        Some(cps_to_interval(self.ramp_cps(cps, now)))
+    }
+
+    // Decides what happens to the click due at 'now', how long it's held down
+    // and when the next one is. Both the clicker thread and the analysis go
+    // through this, so that a seed gives the same clicks in both
+    pub fn next_click(&mut self, now: Instant) -> Option<Click> {
+        if !self.action.is_click() {
+            return None;
+        }
+
+        let artifact = match &self.artifacts {
+            Some(a) => a.roll(&mut self.rngs.artifacts),
+            None => None,
+        };
+
+        let skip = artifact == Some(Artifact::Miss);
+
+        let hold = match &self.hold {
+            Some(h) if !skip => Some(millis_to_duration(h.sample(&mut self.rngs.holds))),
+            _ => None,
+        };
+
+        let interval = match artifact {
+            Some(Artifact::Double(delay)) => delay,
+            Some(Artifact::Pause(pause)) => self.next_interval(now)? + pause,
+            _ => self.next_interval(now)?,
+        };
+
+        Some(Click {
+            skip,
+            hold,
+            interval,
+        })
+    }
+
+    // The inputs to grab when the command consumes them, with the modifier mask
+    // to grab them with. Modifier keys are never grabbed, since that would take
+    // them away from all typing
+    pub fn consumed_inputs(&self) -> Vec<(&ClickerInput, u32)> {
+        if !self.consume {
+            return vec![];
+        }
+
+        // The modifier keys of a chord are held while its other inputs are
+        // pressed, so they are part of the mask
+        let chord_modifiers = self.listen.iter().filter_map(ClickerInput::modifier);
+        let mask = self
+            .modifiers
+            .iter()
+            .copied()
+            .chain(chord_modifiers)
+            .fold(0, |mask, modifier| mask | modifier.mask());
+
+        self.listen
+            .iter()
+            .filter(|input| input.modifier().is_none())
+            .map(|input| (input, mask))
+            .collect()
+    }
+
+    // The instant at which the clicker thread has to handle this command again.
+    // NOTE: A command without a 'next_action' is due immediately, which makes
+    // the first click after an activation instantaneous
+    pub fn deadline(&self, now: Instant) -> Option<Instant> {
+        let is_clicking = self.is_clicking(now);
+        let jitter = match &self.jitter {
+            Some(j) if is_clicking => Some(j.next_move.unwrap_or(now)),
+            _ => None,
+        };
+
+        // No click can happen while one is still held down
+        let next_action = if self.release_at.is_some() {
+            self.release_at
+        } else if is_clicking && (self.action.is_click() || self.action.is_macro()) {
+            Some(self.next_action.unwrap_or(now))
+        } else {
+            None
+        };
+
+        // NOTE: The pointer keeps following its path after a deactivation, so
+        // that it gets back to where it was
+        let motion = self.targets.as_ref().and_then(|t| t.next_move);
+
+        [next_action, self.stop_at(), jitter, motion]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+}
+
+// A separate generator for everything a command randomizes. How often some of
+// them draw depends on when the clicker thread wakes up (e.g. the jitter and the
+// fatigue wander), which must not change the values drawn by the others
+#[derive(Debug)]
+pub struct Rngs {
+    pub intervals: StdRng,
+    pub holds: StdRng,
+    pub artifacts: StdRng,
+    pub fatigue: StdRng,
+    pub jitter: StdRng,
+    pub targets: StdRng,
+    pub r#macro: StdRng,
+}
+
+impl Rngs {
+    // NOTE: The order of the fields is part of what a seed produces
+    fn new(seeder: &mut StdRng) -> Self {
+        let mut next = || StdRng::seed_from_u64(seeder.gen());
+        Self {
+            intervals: next(),
+            holds: next(),
+            artifacts: next(),
+            fatigue: next(),
+            jitter: next(),
+            targets: next(),
+            r#macro: next(),
+        }
+    }
+}
+
+pub struct Click {
+    // Whether the click is missed and shouldn't be sent
+    pub skip: bool,
+    // How long the click is held down, if it isn't released right away
+    pub hold: Option<Duration>,
+    // Time until the next click
+    pub interval: Duration,
+}
+
+pub struct ClickerState {
+    pub commands: Vec<ClickerCommand>,
+    // Every key and button that is held down
+    pub held: HashSet<ClickerInput>,
+}
+
+impl ClickerState {
+    pub fn parse(settings: &Settings) -> Option<Self> {
+        let mut clicker_cmds = vec![];
+        for index in 0..settings.commands.len() {
+            clicker_cmds.push(ClickerCommand::parse(settings, index)?);
         }
 
         Some(Self {
             commands: clicker_cmds,
+            held: HashSet::new(),
         })
     }
+
+    // Renames the listened keys after the unshifted keysym of their key, since
+    // key presses are reported with that name. Otherwise a listened "E" would
+    // never match a press of E, which is reported as "e"
+    pub fn normalize_keys(&mut self, keymap: &Keymap) {
+        for cmd in &mut self.commands {
+            for input in &mut cmd.listen {
+                let ClickerInput::Key(key) = input else {
+                    continue;
+                };
+                match keymap.unshifted_name(key) {
+                    Some(name) => *key = name,
+                    None => info!(
+                        "Key {:?} isn't on the keyboard, it will never be pressed",
+                        key
+                    ),
+                }
+            }
+        }
+    }
+
+    // The modifiers held apart from the inputs of the command's own chord
+    fn modifiers(&self, cmd: &ClickerCommand) -> BTreeSet<Modifier> {
+        self.held
+            .iter()
+            .filter(|input| !cmd.listen.contains(input))
+            .filter_map(ClickerInput::modifier)
+            .collect()
+    }
+
+    // Finds the command whose chord is completed by pressing 'input' on
+    // 'device' while exactly its modifiers are held. A command bound to that
+    // device takes precedence over one listening to any device, then a longer
+    // chord over a shorter one
+    pub fn press(&mut self, input: &ClickerInput, device: &str) -> Option<&mut ClickerCommand> {
+        // Repeated presses of a key that is held down are ignored
+        if !self.held.insert(input.clone()) {
+            return None;
+        }
+
+        let index = self
+            .commands
+            .iter()
+            .enumerate()
+            .filter(|(_, cmd)| {
+                cmd.listen.contains(input)
+                    && cmd.listen.iter().all(|i| self.held.contains(i))
+                    && cmd.device.as_deref().is_none_or(|d| d == device)
+                    && self.modifiers(cmd) == cmd.modifiers
+            })
+            .min_by_key(|(_, cmd)| (cmd.device.is_none(), Reverse(cmd.listen.len())))
+            .map(|(index, _)| index)?;
+
+        let cmd = &mut self.commands[index];
+        cmd.is_triggered = true;
+        Some(cmd)
+    }
+
+    // Finds the commands whose triggered chord is ended by releasing 'input'
+    pub fn release(&mut self, input: &ClickerInput) -> Vec<&mut ClickerCommand> {
+        self.held.remove(input);
+
+        let mut released = vec![];
+        for cmd in self.commands.iter_mut() {
+            if cmd.is_triggered && cmd.listen.contains(input) {
+                cmd.is_triggered = false;
+                released.push(cmd);
+            }
+        }
+        released
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Modifier {
+    Control,
+    Shift,
+    Alt,
+    Super,
+}
+
+impl Modifier {
+    // The modifier of a key, by its name
+    pub fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "Control_L" | "Control_R" => Some(Self::Control),
+            "Shift_L" | "Shift_R" => Some(Self::Shift),
+            "Alt_L" | "Alt_R" | "Meta_L" | "Meta_R" => Some(Self::Alt),
+            "Super_L" | "Super_R" => Some(Self::Super),
+            _ => None,
+        }
+    }
+
+    pub fn mask(&self) -> u32 {
+        match self {
+            Self::Control => xlib::ControlMask,
+            Self::Shift => xlib::ShiftMask,
+            Self::Alt => xlib::Mod1Mask,
+            Self::Super => xlib::Mod4Mask,
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "ctrl" | "control" => Some(Self::Control),
+            "shift" => Some(Self::Shift),
+            "alt" => Some(Self::Alt),
+            "super" => Some(Self::Super),
+            _ => None,
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ClickerInput {
     Key(String),
     Button(InputButton),
 }
 
+impl ClickerInput {
+    pub fn parse(input: &Input) -> Option<Self> {
+        match input.r#type {
+            InputType::Key => {
+                // Unknown key names would never match any key
+                string_to_keysym(&input.value)?;
+                Some(Self::Key(input.value.clone()))
+            }
+            InputType::Button => Some(Self::Button(parse_input_button(input.value.clone())?)),
+        }
+    }
+
+    // The modifier of the input, if it's a modifier key
+    pub fn modifier(&self) -> Option<Modifier> {
+        match self {
+            Self::Key(key) => Modifier::from_key(key),
+            Self::Button(_) => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ClickerAction {
-    KeyPress(String),
-    KeyClick(String, RangeInclusive<u32>),
+    KeyPress(Key),
+    KeyClick(Key, CpsGenerator),
     ButtonPress(InputButton),
-    ButtonClick(InputButton, RangeInclusive<u32>),
+    ButtonClick(InputButton, CpsGenerator),
+    Macro(Macro),
+}
+
+impl ClickerAction {
+    pub fn is_click(&self) -> bool {
+        matches!(self, Self::KeyClick(..) | Self::ButtonClick(..))
+    }
+
+    pub fn is_macro(&self) -> bool {
+        matches!(self, Self::Macro(_))
+    }
+
+    pub fn generator_mut(&mut self) -> Option<&mut CpsGenerator> {
+        match self {
+            Self::KeyClick(_, g) | Self::ButtonClick(_, g) => Some(g),
+            _ => None,
+        }
+    }
 }
 
-fn parse_input_button(s: String) -> Option<InputButton> {
+// Parses every input of a listen, along with the modifiers they are combined
+// with (e.g. "ctrl+shift+F")
+fn parse_listen(listen: &Listen) -> Option<(Vec<ClickerInput>, BTreeSet<Modifier>)> {
+    let inputs = match listen {
+        Listen::Input(input) => std::slice::from_ref(input),
+        Listen::Chord(inputs) => inputs.as_slice(),
+    };
+    if inputs.is_empty() {
+        return None;
+    }
+
+    let mut chord = vec![];
+    let mut modifiers = BTreeSet::new();
+    for input in inputs {
+        let mut parts: Vec<&str> = input.value.split('+').collect();
+        let value = parts.pop()?.to_string();
+        for part in parts {
+            modifiers.insert(Modifier::parse(part)?);
+        }
+
+        chord.push(ClickerInput::parse(&Input {
+            r#type: input.r#type.clone(),
+            value,
+        })?);
+    }
+
+    Some((chord, modifiers))
+}
+
+fn parse_sampler(range: &CpsRange, distribution: Option<&Distribution>) -> Option<RangeSampler> {
+    // A CPS of 0 would be an infinitely long interval
+    if range.min <= 0.0 {
+        return None;
+    }
+    RangeSampler::parse(range.min..=range.max, distribution)
+}
+
+// The value that 'parse_input_button' parses into the button
+pub fn input_button_name(button: &InputButton) -> String {
+    match button {
+        InputButton::Left => "L".to_string(),
+        InputButton::Middle => "M".to_string(),
+        InputButton::Right => "R".to_string(),
+        InputButton::Back => "B".to_string(),
+        InputButton::Forward => "F".to_string(),
+        InputButton::ScrollUp => "ScrollUp".to_string(),
+        InputButton::ScrollDown => "ScrollDown".to_string(),
+        InputButton::ScrollLeft => "ScrollLeft".to_string(),
+        InputButton::ScrollRight => "ScrollRight".to_string(),
+        InputButton::Other(code) => code.to_string(),
+    }
+}
+
+pub fn parse_input_button(s: String) -> Option<InputButton> {
     match s.as_str() {
         "L" => Some(InputButton::Left),
         "M" => Some(InputButton::Middle),
         "R" => Some(InputButton::Right),
         "B" => Some(InputButton::Back),
         "F" => Some(InputButton::Forward),
-        _ => None,
+        "ScrollUp" => Some(InputButton::ScrollUp),
+        "ScrollDown" => Some(InputButton::ScrollDown),
+        "ScrollLeft" => Some(InputButton::ScrollLeft),
+        "ScrollRight" => Some(InputButton::ScrollRight),
+        // Any other button by its number, as reported by 'xev' or 'xinput'
+        _ => match s.parse::<u8>() {
+            Ok(code) if code > 0 => Some(InputButton::from(code as u32)),
+            _ => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_cps_ranges_without_a_positive_minimum() {
+        let range = |min, max| CpsRange { min, max };
+        assert!(parse_sampler(&range(0.0, 10.0), None).is_none());
+        assert!(parse_sampler(&range(-5.0, 10.0), None).is_none());
+        assert!(parse_sampler(&range(12.0, 10.0), None).is_none());
+        assert!(parse_sampler(&range(8.0, 12.0), None).is_some());
+    }
+
+    fn state(method: &str) -> ClickerState {
+        let settings = Settings::from_toml(&format!(
+            r#"
+            [[commands]]
+            listen = [{{ type = "Button", value = "B" }}, {{ type = "Key", value = "Shift_L" }}]
+            action = {{ type = "Button", value = "L" }}
+            method = "{}"
+            range = {{ min = 12, max = 18 }}
+            "#,
+            method
+        ))
+        .unwrap();
+        ClickerState::parse(&settings).unwrap()
+    }
+
+    fn parses_with_keys(listen: &str, action: &str) -> bool {
+        let settings = Settings::from_toml(&format!(
+            r#"
+            [[commands]]
+            listen = {{ type = "Key", value = "{}" }}
+            action = {{ type = "Key", value = "{}" }}
+            method = "Toggle"
+            range = {{ min = 12, max = 18 }}
+            "#,
+            listen, action
+        ))
+        .unwrap();
+        ClickerState::parse(&settings).is_some()
+    }
+
+    #[test]
+    fn rejects_unknown_key_names() {
+        assert!(parses_with_keys("ctrl+F8", "space"));
+        assert!(!parses_with_keys("ctrl+F8", "NoSuchKey"));
+        assert!(!parses_with_keys("NoSuchKey", "space"));
+        assert!(!parses_with_keys("ctrl+", "space"));
+    }
+
+    #[test]
+    fn rejects_pointer_settings_without_a_position() {
+        let parses = |settings: &str| {
+            let settings = Settings::from_toml(&format!(
+                r#"
+                [[commands]]
+                listen = {{ type = "Button", value = "B" }}
+                action = {{ type = "Button", value = "L" }}
+                method = "Toggle"
+                range = {{ min = 12, max = 18 }}
+                {}
+                "#,
+                settings
+            ))
+            .unwrap();
+            ClickerState::parse(&settings).is_some()
+        };
+
+        assert!(parses(
+            "position = { x = 10, y = 20 }\nrestore_pointer = true"
+        ));
+        assert!(parses(
+            "position = { x = 10, y = 20 }\npointer_motion = \"Path\""
+        ));
+        assert!(!parses("restore_pointer = true"));
+        assert!(!parses("pointer_motion = \"Jump\""));
+    }
+
+    #[test]
+    fn consumes_only_chords_with_one_grabbed_input() {
+        let parses = |listen: &str| {
+            let settings = Settings::from_toml(&format!(
+                r#"
+                [[commands]]
+                listen = {}
+                action = {{ type = "Button", value = "L" }}
+                method = "Toggle"
+                range = {{ min = 12, max = 18 }}
+                consume = true
+                "#,
+                listen
+            ))
+            .unwrap();
+            ClickerState::parse(&settings).map(|state| {
+                let cmd = &state.commands[0];
+                cmd.consumed_inputs().len()
+            })
+        };
+
+        assert_eq!(parses(r#"{ type = "Key", value = "ctrl+F8" }"#), Some(1));
+        assert_eq!(
+            parses(r#"[{ type = "Button", value = "B" }, { type = "Key", value = "Shift_L" }]"#),
+            Some(1)
+        );
+        assert_eq!(
+            parses(r#"[{ type = "Button", value = "B" }, { type = "Key", value = "a" }]"#),
+            None
+        );
+    }
+
+    // Presses or releases an input the way the event handler does
+    fn input(state: &mut ClickerState, input: &ClickerInput, is_press: bool) {
+        let now = Instant::now();
+        if is_press {
+            if let Some(cmd) = state.press(input, "") {
+                cmd.trigger(now);
+            }
+        } else {
+            for cmd in state.release(input) {
+                cmd.untrigger(now);
+            }
+        }
+    }
+
+    #[test]
+    fn toggle_switches_when_the_chord_is_completed() {
+        let back = ClickerInput::Button(InputButton::Back);
+        let shift = ClickerInput::Key("Shift_L".to_string());
+        let mut state = state("Toggle");
+
+        input(&mut state, &shift, true);
+        assert!(!state.commands[0].is_active);
+        input(&mut state, &back, true);
+        assert!(state.commands[0].is_active);
+        input(&mut state, &back, false);
+        assert!(state.commands[0].is_active);
+
+        input(&mut state, &back, true);
+        assert!(!state.commands[0].is_active);
+        input(&mut state, &back, false);
+        input(&mut state, &shift, false);
+        assert!(!state.commands[0].is_active);
+    }
+
+    #[test]
+    fn hold_lasts_while_the_chord_is_held() {
+        let back = ClickerInput::Button(InputButton::Back);
+        let shift = ClickerInput::Key("Shift_L".to_string());
+        let mut state = state("Hold");
+
+        input(&mut state, &back, true);
+        assert!(!state.commands[0].is_active);
+        input(&mut state, &shift, true);
+        assert!(state.commands[0].is_active);
+        input(&mut state, &shift, false);
+        assert!(!state.commands[0].is_active);
+    }
+
+    #[test]
+    fn clicks_carry_their_hold_duration() {
+        let settings = Settings::from_toml(
+            r#"
+            [[commands]]
+            listen = { type = "Button", value = "B" }
+            action = { type = "Button", value = "L" }
+            method = "Hold"
+            range = { min = 12, max = 18 }
+            hold = { min = 40, max = 90 }
+            "#,
+        )
+        .unwrap();
+        let mut cmd = ClickerCommand::parse(&settings, 0).unwrap();
+
+        let now = Instant::now();
+        for _ in 0..100 {
+            let hold = cmd.next_click(now).unwrap().hold.unwrap();
+            assert!((40..=90).contains(&hold.as_millis()));
+        }
     }
 }
@@ -1,51 +1,143 @@
+use crate::inputsys::InputKey;
 use std::ffi::{CStr, CString};
-use std::ptr;
-use x11::xlib::{
-    Display, XCloseDisplay, XKeycodeToKeysym, XKeysymToKeycode, XKeysymToString, XOpenDisplay,
-    XStringToKeysym,
-};
-
-struct DisplayMgr {
-    dpy: *mut Display,
+use x11::xlib::{KeySym, NoSymbol, XKeysymToString, XStringToKeysym};
+use xcb::{x, Connection};
+
+// The keysyms of every keycode, queried once from the X server instead of
+// opening an Xlib display for each conversion
+#[derive(Debug, Default)]
+pub struct Keymap {
+    min_keycode: InputKey,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<x::Keysym>,
 }
 
-impl DisplayMgr {
-    fn new() -> Self {
+impl Keymap {
+    pub fn query(conn: &Connection) -> xcb::Result<Self> {
+        let setup = conn.get_setup();
+        let min_keycode = setup.min_keycode();
+        let reply = conn.wait_for_reply(conn.send_request(&x::GetKeyboardMapping {
+            first_keycode: min_keycode,
+            count: setup.max_keycode() - min_keycode + 1,
+        }))?;
+
+        Ok(Self {
+            min_keycode,
+            keysyms_per_keycode: reply.keysyms_per_keycode() as usize,
+            keysyms: reply.keysyms().to_vec(),
+        })
+    }
+
+    // The keysyms of a keycode, one for each shift level
+    fn keysyms(&self, keycode: InputKey) -> &[x::Keysym] {
+        let Some(offset) = keycode.checked_sub(self.min_keycode) else {
+            return &[];
+        };
+
+        let start = offset as usize * self.keysyms_per_keycode;
+        self.keysyms
+            .get(start..start + self.keysyms_per_keycode)
+            .unwrap_or(&[])
+    }
+
+    pub fn keycode_to_string(&self, keycode: InputKey) -> String {
+        let keysym = self.keysyms(keycode).first().copied().unwrap_or(0);
         unsafe {
-            Self {
-                dpy: XOpenDisplay(ptr::null()),
+            let keystring_ptr = XKeysymToString(keysym as KeySym);
+            if keystring_ptr.is_null() {
+                return "".to_string();
             }
+            String::from(CStr::from_ptr(keystring_ptr).to_str().unwrap_or(""))
         }
     }
-}
 
-impl Drop for DisplayMgr {
-    fn drop(&mut self) {
-        unsafe {
-            XCloseDisplay(self.dpy);
+    // The keycode that types 'keysym', if any key of the keyboard does
+    pub fn keysym_to_keycode(&self, keysym: x::Keysym) -> Option<InputKey> {
+        if self.keysyms_per_keycode == 0 {
+            return None;
         }
+
+        // NOTE: Same search as 'XKeysymToKeycode', the unshifted keysyms of
+        // every keycode are checked first, then the shifted ones and so on
+        let keycodes = self.keysyms.len() / self.keysyms_per_keycode;
+        for level in 0..self.keysyms_per_keycode {
+            for offset in 0..keycodes {
+                if self.keysyms[offset * self.keysyms_per_keycode + level] == keysym {
+                    return Some(self.min_keycode + offset as InputKey);
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn string_to_keycode(&self, s: &str) -> Option<InputKey> {
+        self.keysym_to_keycode(string_to_keysym(s)?)
+    }
+
+    // The name of the unshifted keysym of the key that types 's' (e.g. "e" for
+    // "E"), which is the name the presses of that key are reported with
+    pub fn unshifted_name(&self, s: &str) -> Option<String> {
+        Some(self.keycode_to_string(self.string_to_keycode(s)?))
     }
 }
 
-// TODO: Take display pointer from the XCB connection
-pub fn keycode_to_string(keycode: u8) -> String {
-    unsafe {
-        let mgr = DisplayMgr::new();
-        let keysym = XKeycodeToKeysym(mgr.dpy, keycode, 0);
-        let keystring_ptr = XKeysymToString(keysym);
-        if keystring_ptr.is_null() {
-            return "".to_string();
-        }
-        let keystring = String::from(CStr::from_ptr(keystring_ptr).to_str().unwrap_or(""));
-        keystring
+// The keysym of a key name (e.g. "Shift_L" or "a"), or None if there is no such
+// keysym. Unlike the keycode, it doesn't depend on the keyboard mapping
+pub fn string_to_keysym(s: &str) -> Option<x::Keysym> {
+    let c_str = CString::new(s).ok()?;
+    let keysym = unsafe { XStringToKeysym(c_str.as_ptr()) };
+    if keysym == NoSymbol as KeySym {
+        return None;
     }
+    Some(keysym as x::Keysym)
 }
 
-pub fn string_to_keycode(s: &String) -> u8 {
-    unsafe {
-        let c_str = CString::new(s.to_owned()).unwrap();
-        let keysym = XStringToKeysym(c_str.as_ptr());
-        let mgr = DisplayMgr::new();
-        XKeysymToKeycode(mgr.dpy, keysym)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two keycodes starting at 10 with two levels each: 'a'/'A' and 'b'/'a'
+    fn keymap() -> Keymap {
+        let (a, b) = (
+            string_to_keysym("a").unwrap(),
+            string_to_keysym("b").unwrap(),
+        );
+        Keymap {
+            min_keycode: 10,
+            keysyms_per_keycode: 2,
+            keysyms: vec![a, string_to_keysym("A").unwrap(), b, a],
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_key_names() {
+        assert!(string_to_keysym("Shift_L").is_some());
+        assert!(string_to_keysym("NoSuchKey").is_none());
+        assert!(string_to_keysym("").is_none());
+    }
+
+    #[test]
+    fn unshifted_keysyms_come_first() {
+        let keymap = keymap();
+        assert_eq!(keymap.string_to_keycode("a"), Some(10));
+        assert_eq!(keymap.string_to_keycode("A"), Some(10));
+        assert_eq!(keymap.string_to_keycode("b"), Some(11));
+        assert_eq!(keymap.keycode_to_string(11), "b");
+    }
+
+    #[test]
+    fn shifted_names_become_unshifted() {
+        let keymap = keymap();
+        assert_eq!(keymap.unshifted_name("A").as_deref(), Some("a"));
+        assert_eq!(keymap.unshifted_name("a").as_deref(), Some("a"));
+        assert_eq!(keymap.unshifted_name("b").as_deref(), Some("b"));
+        assert_eq!(keymap.unshifted_name("space"), None);
+    }
+
+    #[test]
+    fn keys_missing_from_the_keyboard_have_no_keycode() {
+        assert_eq!(keymap().string_to_keycode("space"), None);
+        assert_eq!(Keymap::default().string_to_keycode("space"), None);
     }
 }
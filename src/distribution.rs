@@ -0,0 +1,218 @@
+use crate::settings::{Distribution, DistributionType};
+use rand::Rng;
+use rand_distr::{LogNormal, Normal};
+use std::ops::RangeInclusive;
+
+// Amount of draws a truncated distribution gets to land inside the range
+// before the sample is clamped instead
+const MAX_TRUNCATED_DRAWS: u32 = 32;
+
+#[derive(Debug)]
+enum SamplerKind {
+    Uniform,
+    Normal(Normal<f64>),
+    LogNormal(LogNormal<f64>),
+    // Recorded values, smoothed with a normal kernel so that the exact same
+    // values don't keep coming back
+    Empirical(Vec<f64>, Normal<f64>),
+}
+
+#[derive(Debug)]
+pub struct RangeSampler {
+    pub range: RangeInclusive<f64>,
+    pub mean: f64,
+    kind: SamplerKind,
+    truncated: bool,
+}
+
+impl RangeSampler {
+    pub fn parse(range: RangeInclusive<f64>, distribution: Option<&Distribution>) -> Option<Self> {
+        let (min, max) = (*range.start(), *range.end());
+        if !min.is_finite() || !max.is_finite() || min < 0.0 || min > max {
+            return None;
+        }
+
+        let uniform_mean = (range.start() + range.end()) / 2.0;
+        let Some(dist) = distribution else {
+            return Some(Self {
+                range,
+                mean: uniform_mean,
+                kind: SamplerKind::Uniform,
+                truncated: true,
+            });
+        };
+
+        // NOTE: 'Normal::new' accepts a NaN mean, whose samples would all be
+        // NaN. The checks are written so that NaN fails them too
+        let is_stddev = |stddev: f64| (0.0..f64::INFINITY).contains(&stddev);
+        if !dist.mean.is_none_or(f64::is_finite) || !dist.stddev.is_none_or(is_stddev) {
+            return None;
+        }
+
+        let kind = match dist.r#type {
+            DistributionType::Uniform => SamplerKind::Uniform,
+            DistributionType::Normal => {
+                SamplerKind::Normal(Normal::new(dist.mean?, dist.stddev?).ok()?)
+            }
+            DistributionType::LogNormal => {
+                // 'mean' and 'stddev' describe the resulting values, not the
+                // underlying normal distribution, so they are converted here
+                let mean = dist.mean?;
+                LogNormal::from_mean_cv(mean, dist.stddev? / mean)
+                    .map(SamplerKind::LogNormal)
+                    .ok()?
+            }
+        };
+
+        let truncated = dist.truncated || dist.r#type == DistributionType::Uniform;
+        let mean = match dist.r#type {
+            DistributionType::Uniform => uniform_mean,
+            // NOTE: Truncating shifts the mean, this is only an approximation
+            _ if truncated => dist.mean?.clamp(*range.start(), *range.end()),
+            _ => dist.mean?,
+        };
+
+        Some(Self {
+            range,
+            mean,
+            kind,
+            truncated,
+        })
+    }
+
+    pub fn empirical(values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        // Silverman's rule of thumb
+        let bandwidth = 1.06 * variance.sqrt() * n.powf(-0.2);
+
+        let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        Some(Self {
+            range: min..=max,
+            mean,
+            kind: SamplerKind::Empirical(values, Normal::new(0.0, bandwidth).ok()?),
+            truncated: false,
+        })
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        let mut value = self.draw(rng);
+        if self.truncated {
+            let mut draws = 1;
+            while !self.range.contains(&value) && draws < MAX_TRUNCATED_DRAWS {
+                value = self.draw(rng);
+                draws += 1;
+            }
+        }
+
+        self.bound(value)
+    }
+
+    // Brings a value derived from this sampler's output back into the values it
+    // is allowed to produce
+    pub fn bound(&self, mut value: f64) -> f64 {
+        if self.truncated {
+            value = value.clamp(*self.range.start(), *self.range.end());
+        }
+
+        // Non-positive values have no meaning for rates and durations
+        if value <= 0.0 {
+            *self.range.start()
+        } else {
+            value
+        }
+    }
+
+    fn draw<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match &self.kind {
+            SamplerKind::Uniform => rng.gen_range(self.range.clone()),
+            SamplerKind::Normal(d) => rng.sample(d),
+            SamplerKind::LogNormal(d) => rng.sample(d),
+            SamplerKind::Empirical(values, kernel) => {
+                values[rng.gen_range(0..values.len())] + rng.sample(kernel)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn distribution(r#type: DistributionType, truncated: bool) -> Distribution {
+        Distribution {
+            r#type,
+            mean: Some(15.0),
+            stddev: Some(4.0),
+            truncated,
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!(RangeSampler::parse(5.0..=4.0, None).is_none());
+        assert!(RangeSampler::parse(-1.0..=4.0, None).is_none());
+        assert!(RangeSampler::parse(f64::NAN..=4.0, None).is_none());
+        assert!(RangeSampler::parse(1.0..=f64::INFINITY, None).is_none());
+        assert!(RangeSampler::parse(0.0..=0.0, None).is_some());
+    }
+
+    #[test]
+    fn rejects_incomplete_distributions() {
+        let mut dist = distribution(DistributionType::Normal, true);
+        dist.stddev = None;
+        assert!(RangeSampler::parse(10.0..=20.0, Some(&dist)).is_none());
+        assert!(RangeSampler::empirical(vec![]).is_none());
+    }
+
+    #[test]
+    fn rejects_non_finite_parameters() {
+        for r#type in [DistributionType::Normal, DistributionType::LogNormal] {
+            let parses = |mean, stddev| {
+                let mut dist = distribution(r#type.clone(), true);
+                (dist.mean, dist.stddev) = (Some(mean), Some(stddev));
+                RangeSampler::parse(10.0..=20.0, Some(&dist)).is_some()
+            };
+            assert!(parses(15.0, 4.0));
+            assert!(!parses(f64::NAN, 4.0));
+            assert!(!parses(f64::INFINITY, 4.0));
+            assert!(!parses(15.0, f64::NAN));
+            assert!(!parses(15.0, f64::INFINITY));
+            assert!(!parses(15.0, -4.0));
+        }
+    }
+
+    #[test]
+    fn truncated_samples_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for dist in [
+            distribution(DistributionType::Uniform, false),
+            distribution(DistributionType::Normal, true),
+            distribution(DistributionType::LogNormal, true),
+        ] {
+            let sampler = RangeSampler::parse(12.0..=18.0, Some(&dist)).unwrap();
+            for _ in 0..1000 {
+                assert!((12.0..=18.0).contains(&sampler.sample(&mut rng)));
+            }
+        }
+    }
+
+    #[test]
+    fn untruncated_samples_stay_positive() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut dist = distribution(DistributionType::Normal, false);
+        dist.stddev = Some(20.0);
+        let sampler = RangeSampler::parse(12.0..=18.0, Some(&dist)).unwrap();
+        for _ in 0..1000 {
+            assert!(sampler.sample(&mut rng) > 0.0);
+        }
+    }
+}
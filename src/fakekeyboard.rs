@@ -1,11 +1,13 @@
-use crate::inputsys::{InputKey, InputSystem};
+use crate::convert::string_to_keysym;
+use crate::inputsys::{HeldInput, InputKey, InputSystem};
+use log::info;
 use x11::xlib;
 use xcb::{x, xtest, Connection, Xid};
 
 fn key_down(conn: &Connection, key: InputKey) {
     conn.send_request(&xtest::FakeInput {
         r#type: xlib::KeyPress as u8,
-        detail: key as u8,
+        detail: key,
         time: x::CURRENT_TIME,
         root: x::Window::none(),
         root_x: 0,
@@ -17,7 +19,7 @@ fn key_down(conn: &Connection, key: InputKey) {
 fn key_up(conn: &Connection, key: InputKey) {
     conn.send_request(&xtest::FakeInput {
         r#type: xlib::KeyRelease as u8,
-        detail: key as u8,
+        detail: key,
         time: x::CURRENT_TIME,
         root: x::Window::none(),
         root_x: 0,
@@ -28,12 +30,14 @@ fn key_up(conn: &Connection, key: InputKey) {
 
 pub fn press(sys: &InputSystem, key: InputKey) -> xcb::Result<()> {
     key_down(&sys.conn, key);
+    sys.set_held(HeldInput::Key(key), true);
     sys.conn.flush()?;
     Ok(())
 }
 
 pub fn release(sys: &InputSystem, key: InputKey) -> xcb::Result<()> {
     key_up(&sys.conn, key);
+    sys.set_held(HeldInput::Key(key), false);
     sys.conn.flush()?;
     Ok(())
 }
@@ -44,3 +48,54 @@ pub fn click(sys: &InputSystem, key: InputKey) -> xcb::Result<()> {
     sys.conn.flush()?;
     Ok(())
 }
+
+// The key that types 'keysym' with the current keyboard mapping, if there is one
+pub fn keycode(sys: &InputSystem, keysym: x::Keysym) -> Option<InputKey> {
+    let keycode = sys.keymap.read().unwrap().keysym_to_keycode(keysym);
+    if keycode.is_none() {
+        info!("No key types keysym {:#x}, skipping it", keysym);
+    }
+    keycode
+}
+
+// A key sent by a command. It's looked up each time it's pressed, so that it
+// follows changes of the keyboard mapping
+#[derive(Debug)]
+pub struct Key {
+    keysym: x::Keysym,
+    // The keycode it's held down with, released even if the mapping changed
+    // since
+    pressed: Option<InputKey>,
+}
+
+impl Key {
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(Self {
+            keysym: string_to_keysym(s)?,
+            pressed: None,
+        })
+    }
+
+    pub fn press(&mut self, sys: &InputSystem) -> xcb::Result<()> {
+        self.release(sys)?;
+        self.pressed = keycode(sys, self.keysym);
+        match self.pressed {
+            Some(k) => press(sys, k),
+            None => Ok(()),
+        }
+    }
+
+    pub fn release(&mut self, sys: &InputSystem) -> xcb::Result<()> {
+        match self.pressed.take() {
+            Some(k) => release(sys, k),
+            None => Ok(()),
+        }
+    }
+
+    pub fn click(&self, sys: &InputSystem) -> xcb::Result<()> {
+        match keycode(sys, self.keysym) {
+            Some(k) => click(sys, k),
+            None => Ok(()),
+        }
+    }
+}
@@ -1,11 +1,11 @@
-use crate::inputsys::{InputButton, InputSystem};
+use crate::inputsys::{HeldInput, InputButton, InputSystem};
 use x11::xlib;
 use xcb::{x, xtest, Connection, Xid};
 
 fn button_down(conn: &Connection, btn: &InputButton) {
     conn.send_request(&xtest::FakeInput {
         r#type: xlib::ButtonPress as u8,
-        detail: *btn as u8,
+        detail: btn.code() as u8,
         time: x::CURRENT_TIME,
         root: x::Window::none(),
         root_x: 0,
@@ -17,7 +17,7 @@ fn button_down(conn: &Connection, btn: &InputButton) {
 fn button_up(conn: &Connection, btn: &InputButton) {
     conn.send_request(&xtest::FakeInput {
         r#type: xlib::ButtonRelease as u8,
-        detail: *btn as u8,
+        detail: btn.code() as u8,
         time: x::CURRENT_TIME,
         root: x::Window::none(),
         root_x: 0,
@@ -28,12 +28,14 @@ fn button_up(conn: &Connection, btn: &InputButton) {
 
 pub fn press(sys: &InputSystem, btn: &InputButton) -> xcb::Result<()> {
     button_down(&sys.conn, btn);
+    sys.set_held(HeldInput::Button(*btn), true);
     sys.conn.flush()?;
     Ok(())
 }
 
 pub fn release(sys: &InputSystem, btn: &InputButton) -> xcb::Result<()> {
     button_up(&sys.conn, btn);
+    sys.set_held(HeldInput::Button(*btn), false);
     sys.conn.flush()?;
     Ok(())
 }
@@ -44,3 +46,33 @@ pub fn click(sys: &InputSystem, btn: &InputButton) -> xcb::Result<()> {
     sys.conn.flush()?;
     Ok(())
 }
+
+// Moves the pointer to 'x' and 'y' on the screen it's on
+pub fn move_absolute(sys: &InputSystem, x: i16, y: i16) -> xcb::Result<()> {
+    sys.conn.send_request(&xtest::FakeInput {
+        r#type: xlib::MotionNotify as u8,
+        detail: 0, // Absolute motion
+        time: x::CURRENT_TIME,
+        root: x::Window::none(),
+        root_x: x,
+        root_y: y,
+        deviceid: 0,
+    });
+    sys.conn.flush()?;
+    Ok(())
+}
+
+// Moves the pointer by 'dx' and 'dy' pixels from its current position
+pub fn move_relative(sys: &InputSystem, dx: i16, dy: i16) -> xcb::Result<()> {
+    sys.conn.send_request(&xtest::FakeInput {
+        r#type: xlib::MotionNotify as u8,
+        detail: 1, // Relative motion
+        time: x::CURRENT_TIME,
+        root: x::Window::none(),
+        root_x: dx,
+        root_y: dy,
+        deviceid: 0,
+    });
+    sys.conn.flush()?;
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use crate::settings;
+use rand::Rng;
+use rand_distr::StandardNormal;
+use std::time::Instant;
+
+// How fast the random wander is pulled back towards no drift, per second
+const WANDER_REVERSION: f64 = 0.2;
+
+// Scales the CPS of a command down the longer it stays active, and lets it
+// recover while the command is inactive
+#[derive(Debug)]
+pub struct Fatigue {
+    rate: f64,
+    floor: f64,
+    recovery: f64,
+    wander: f64,
+    level: f64,
+    drift: f64,
+    was_active: bool,
+    last_update: Option<Instant>,
+}
+
+impl Fatigue {
+    pub fn parse(fatigue: &settings::Fatigue) -> Option<Self> {
+        // NOTE: The checks are written so that NaN fails them too. A floor of
+        // 0 would end up with a CPS of 0, an infinitely long interval
+        let is_rate = |value: f64| (0.0..f64::INFINITY).contains(&value);
+        let is_valid = is_rate(fatigue.rate)
+            && is_rate(fatigue.recovery)
+            && is_rate(fatigue.wander)
+            && fatigue.floor > 0.0
+            && fatigue.floor <= 1.0;
+        if !is_valid {
+            return None;
+        }
+
+        Some(Self {
+            rate: fatigue.rate,
+            floor: fatigue.floor,
+            recovery: fatigue.recovery,
+            wander: fatigue.wander,
+            level: 1.0,
+            drift: 0.0,
+            was_active: false,
+            last_update: None,
+        })
+    }
+
+    // Advances the model up to 'now'. The time since the last update is
+    // accounted to the activation state of the last update, so this has to be
+    // called whenever the command changes state
+    pub fn update<R: Rng + ?Sized>(&mut self, is_active: bool, now: Instant, rng: &mut R) {
+        if let Some(last) = self.last_update {
+            let dt = now.saturating_duration_since(last).as_secs_f64();
+            if self.was_active {
+                self.level = (self.level - self.rate * dt).max(self.floor);
+                let noise: f64 = rng.sample(StandardNormal);
+                self.drift += noise * self.wander * dt.sqrt() - self.drift * WANDER_REVERSION * dt;
+            } else {
+                self.level = (self.level + self.recovery * dt).min(1.0);
+                self.drift -= self.drift * (self.recovery * dt).min(1.0);
+            }
+        }
+
+        self.was_active = is_active;
+        self.last_update = Some(now);
+    }
+
+    // The factor the CPS of the command is multiplied with
+    pub fn multiplier(&self) -> f64 {
+        (self.level + self.drift).max(self.floor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::time::Duration;
+
+    fn fatigue(rate: f64, floor: f64, recovery: f64, wander: f64) -> Option<Fatigue> {
+        Fatigue::parse(&settings::Fatigue {
+            rate,
+            floor,
+            recovery,
+            wander,
+        })
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(fatigue(0.01, 0.8, 0.02, 0.03).is_some());
+        assert!(fatigue(0.01, 1.0, 0.02, 0.0).is_some());
+        assert!(fatigue(1.0, 0.0, 0.02, 0.0).is_none());
+        assert!(fatigue(0.01, 1.5, 0.02, 0.0).is_none());
+        assert!(fatigue(-0.01, 0.8, 0.02, 0.0).is_none());
+        assert!(fatigue(f64::NAN, 0.8, 0.02, 0.0).is_none());
+        assert!(fatigue(0.01, f64::NAN, 0.02, 0.0).is_none());
+        assert!(fatigue(0.01, 0.8, f64::INFINITY, 0.0).is_none());
+        assert!(fatigue(0.01, 0.8, 0.02, f64::NAN).is_none());
+    }
+
+    #[test]
+    fn multiplier_never_drops_below_the_floor() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut f = fatigue(1.0, 0.05, 0.02, 0.5).unwrap();
+        let start = Instant::now();
+        for i in 0..1000 {
+            f.update(true, start + Duration::from_millis(100 * i), &mut rng);
+            assert!(f.multiplier() >= 0.05);
+        }
+    }
+}
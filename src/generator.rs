@@ -0,0 +1,208 @@
+use crate::distribution::RangeSampler;
+use crate::fatigue::Fatigue;
+use crate::settings::{self, ModelType};
+use rand::distributions::WeightedIndex;
+use rand::Rng;
+use std::time::Instant;
+
+#[derive(Debug)]
+enum Model {
+    // Each CPS is pulled towards the previous one by 'phi'
+    AR1 {
+        phi: f64,
+        last: Option<f64>,
+    },
+    // The range is split into equally sized buckets, from slowest to fastest,
+    // and the next bucket is picked by the transition row of the current one
+    Markov {
+        transitions: Vec<WeightedIndex<f64>>,
+        state: usize,
+    },
+}
+
+// Picks the CPS of every click of a command
+#[derive(Debug)]
+pub struct CpsGenerator {
+    sampler: RangeSampler,
+    model: Option<Model>,
+    fatigue: Option<Fatigue>,
+}
+
+impl CpsGenerator {
+    pub fn parse(
+        sampler: RangeSampler,
+        model: Option<&settings::Model>,
+        fatigue: Option<&settings::Fatigue>,
+    ) -> Option<Self> {
+        let model = match model {
+            Some(m) => Some(parse_model(m)?),
+            None => None,
+        };
+
+        let fatigue = match fatigue {
+            Some(f) => Some(Fatigue::parse(f)?),
+            None => None,
+        };
+
+        Some(Self {
+            sampler,
+            model,
+            fatigue,
+        })
+    }
+
+    pub fn update<R: Rng + ?Sized>(&mut self, is_active: bool, now: Instant, rng: &mut R) {
+        if let Some(f) = &mut self.fatigue {
+            f.update(is_active, now, rng);
+        }
+    }
+
+    pub fn next_cps<R: Rng + ?Sized>(&mut self, rng: &mut R) -> f64 {
+        let cps = match &mut self.model {
+            None => self.sampler.sample(rng),
+
+            Some(Model::AR1 { phi, last }) => {
+                let fresh = self.sampler.sample(rng);
+                // Keeps the mean and the spread of the sampler, only the
+                // consecutive values become correlated
+                let mean = self.sampler.mean;
+                let cps = match last {
+                    Some(prev) => self.sampler.bound(
+                        mean + *phi * (*prev - mean) + (1.0 - *phi * *phi).sqrt() * (fresh - mean),
+                    ),
+                    None => fresh,
+                };
+                *last = Some(cps);
+                cps
+            }
+
+            Some(Model::Markov { transitions, state }) => {
+                *state = rng.sample(&transitions[*state]);
+                let (min, max) = (*self.sampler.range.start(), *self.sampler.range.end());
+                let width = (max - min) / transitions.len() as f64;
+                let low = min + width * *state as f64;
+                self.sampler.bound(rng.gen_range(low..=low + width))
+            }
+        };
+
+        match &self.fatigue {
+            Some(f) => cps * f.multiplier(),
+            None => cps,
+        }
+    }
+}
+
+fn parse_model(model: &settings::Model) -> Option<Model> {
+    match model.r#type {
+        ModelType::AR1 => {
+            let phi = model.phi?;
+            if !(0.0..1.0).contains(&phi) {
+                return None;
+            }
+
+            Some(Model::AR1 { phi, last: None })
+        }
+
+        ModelType::Markov => {
+            let rows = model.transitions.as_ref()?;
+            if rows.is_empty() || rows.iter().any(|row| row.len() != rows.len()) {
+                return None;
+            }
+
+            let transitions = rows
+                .iter()
+                .map(|row| WeightedIndex::new(row).ok())
+                .collect::<Option<Vec<_>>>()?;
+
+            Some(Model::Markov {
+                state: transitions.len() / 2,
+                transitions,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn generator(model: settings::Model) -> Option<CpsGenerator> {
+        let sampler = RangeSampler::parse(10.0..=20.0, None)?;
+        CpsGenerator::parse(sampler, Some(&model), None)
+    }
+
+    fn ar1(phi: f64) -> settings::Model {
+        settings::Model {
+            r#type: ModelType::AR1,
+            phi: Some(phi),
+            transitions: None,
+        }
+    }
+
+    fn markov(transitions: Vec<Vec<f64>>) -> settings::Model {
+        settings::Model {
+            r#type: ModelType::Markov,
+            phi: None,
+            transitions: Some(transitions),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_models() {
+        assert!(generator(ar1(1.0)).is_none());
+        assert!(generator(ar1(-0.1)).is_none());
+        assert!(generator(markov(vec![])).is_none());
+        assert!(generator(markov(vec![vec![1.0, 0.0]])).is_none());
+        assert!(generator(markov(vec![vec![0.0, 0.0], vec![1.0, 1.0]])).is_none());
+    }
+
+    #[test]
+    fn models_stay_within_the_range() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let transitions = vec![
+            vec![0.8, 0.2, 0.0],
+            vec![0.1, 0.8, 0.1],
+            vec![0.0, 0.2, 0.8],
+        ];
+        for model in [ar1(0.9), markov(transitions)] {
+            let mut g = generator(model).unwrap();
+            for _ in 0..1000 {
+                let cps = g.next_cps(&mut rng);
+                assert!((10.0..=20.0).contains(&cps), "{} is out of range", cps);
+            }
+        }
+    }
+
+    #[test]
+    fn ar1_correlates_consecutive_values() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut g = generator(ar1(0.9)).unwrap();
+        let values: Vec<f64> = (0..2000).map(|_| g.next_cps(&mut rng)).collect();
+
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let (mut covariance, mut variance) = (0.0, 0.0);
+        for pair in values.windows(2) {
+            covariance += (pair[0] - mean) * (pair[1] - mean);
+            variance += (pair[0] - mean).powi(2);
+        }
+        assert!(covariance / variance > 0.5);
+    }
+
+    #[test]
+    fn markov_stays_in_a_bucket_it_cant_leave() {
+        let mut rng = StdRng::seed_from_u64(9);
+        // Starts in the middle bucket, 13.33 to 16.67 CPS
+        let mut g = generator(markov(vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ]))
+        .unwrap();
+        for _ in 0..1000 {
+            let cps = g.next_cps(&mut rng);
+            assert!((13.3..=16.7).contains(&cps), "{} left the bucket", cps);
+        }
+    }
+}
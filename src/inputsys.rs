@@ -1,11 +1,12 @@
-use std::sync::Arc;
+use crate::convert::Keymap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
 use std::thread;
-use std::time::Duration;
 use x11::xlib;
 use xcb::{
     x,
     xinput::{self, XiEventMask},
-    Connection, Extension,
+    Connection, Extension, Xid,
 };
 
 const MOUSE_LEFT: u32 = xlib::Button1;
@@ -14,36 +15,59 @@ const MOUSE_RIGHT: u32 = xlib::Button3;
 const MOUSE_SCROLL_UP: u32 = xlib::Button4;
 const MOUSE_SCROLL_DOWN: u32 = xlib::Button5;
 // TODO: Find library definitions of these values
+const MOUSE_SCROLL_LEFT: u32 = 6;
+const MOUSE_SCROLL_RIGHT: u32 = 7;
 const MOUSE_BACK: u32 = 8;
 const MOUSE_FORWARD: u32 = 9;
 
-#[repr(u32)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum InputButton {
     // Normal buttons
-    Left = MOUSE_LEFT,
-    Middle = MOUSE_MIDDLE,
-    Right = MOUSE_RIGHT,
-    ScrollUp = MOUSE_SCROLL_UP,
-    ScrollDown = MOUSE_SCROLL_DOWN,
+    Left,
+    Middle,
+    Right,
+    // Scroll wheel, which sends a press and a release for every notch
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
     // Side/Thumb buttons
-    Back = MOUSE_BACK,
-    Forward = MOUSE_FORWARD,
+    Back,
+    Forward,
+    // Extra buttons of some mice, by their button number
+    Other(u32),
 }
 
-impl TryFrom<u32> for InputButton {
-    type Error = ();
+impl InputButton {
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::Left => MOUSE_LEFT,
+            Self::Middle => MOUSE_MIDDLE,
+            Self::Right => MOUSE_RIGHT,
+            Self::ScrollUp => MOUSE_SCROLL_UP,
+            Self::ScrollDown => MOUSE_SCROLL_DOWN,
+            Self::ScrollLeft => MOUSE_SCROLL_LEFT,
+            Self::ScrollRight => MOUSE_SCROLL_RIGHT,
+            Self::Back => MOUSE_BACK,
+            Self::Forward => MOUSE_FORWARD,
+            Self::Other(code) => *code,
+        }
+    }
+}
 
-    fn try_from(value: u32) -> Result<Self, Self::Error> {
+impl From<u32> for InputButton {
+    fn from(value: u32) -> Self {
         match value {
-            MOUSE_LEFT => Ok(Self::Left),
-            MOUSE_MIDDLE => Ok(Self::Middle),
-            MOUSE_RIGHT => Ok(Self::Right),
-            MOUSE_SCROLL_UP => Ok(Self::ScrollUp),
-            MOUSE_SCROLL_DOWN => Ok(Self::ScrollDown),
-            MOUSE_BACK => Ok(Self::Back),
-            MOUSE_FORWARD => Ok(Self::Forward),
-            _ => Err(()),
+            MOUSE_LEFT => Self::Left,
+            MOUSE_MIDDLE => Self::Middle,
+            MOUSE_RIGHT => Self::Right,
+            MOUSE_SCROLL_UP => Self::ScrollUp,
+            MOUSE_SCROLL_DOWN => Self::ScrollDown,
+            MOUSE_SCROLL_LEFT => Self::ScrollLeft,
+            MOUSE_SCROLL_RIGHT => Self::ScrollRight,
+            MOUSE_BACK => Self::Back,
+            MOUSE_FORWARD => Self::Forward,
+            _ => Self::Other(value),
         }
     }
 }
@@ -55,11 +79,30 @@ pub enum InputEvent {
     KeyRelease(InputKey),
     ButtonPress(InputButton),
     ButtonRelease(InputButton),
+    // The pointer moved. Only sent after 'listen_motion'
+    // NOTE: Raw motion is in device units, so the position on the screen has
+    // to be queried with 'query_pointer'
+    Motion,
+}
+
+// A key or button that was faked as pressed and not released yet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HeldInput {
+    Key(InputKey),
+    Button(InputButton),
 }
 
 pub struct InputSystem {
     pub conn: Arc<Connection>,
     pub window: x::Window,
+    // Names of the input devices by their ids
+    pub devices: HashMap<u16, String>,
+    // Ids of the XTest devices, which send the events faked by this program
+    pub synthetic_devices: Vec<u16>,
+    pub ignore_synthetic: bool,
+    // Kept up to date by the event loop when the keyboard mapping changes
+    pub keymap: Arc<RwLock<Keymap>>,
+    held: Mutex<HashSet<HeldInput>>,
 }
 
 fn connect_xcb() -> xcb::Result<Connection> {
@@ -83,15 +126,69 @@ fn get_root_window(conn: &Connection) -> Option<x::Window> {
     Some(window)
 }
 
-fn setup_xcb_events(conn: &Connection, window: x::Window) -> xcb::Result<()> {
+fn query_devices(conn: &Connection) -> xcb::Result<HashMap<u16, String>> {
+    let reply = conn.wait_for_reply(conn.send_request(&xinput::XiQueryDevice {
+        device: xinput::Device::All,
+    }))?;
+
+    let devices = reply
+        .infos()
+        .map(|info| (info.device().id(), info.name().to_utf8().into_owned()))
+        .collect();
+
+    Ok(devices)
+}
+
+// The ids of the physical slave devices of a kind, leaving out the XTest ones.
+// With a 'name', only the devices called that way
+fn query_slave_devices(
+    conn: &Connection,
+    r#type: xinput::DeviceType,
+    name: Option<&str>,
+) -> xcb::Result<Vec<u16>> {
+    let reply = conn.wait_for_reply(conn.send_request(&xinput::XiQueryDevice {
+        device: xinput::Device::All,
+    }))?;
+
+    let devices = reply
+        .infos()
+        .filter(|info| {
+            let info_name = info.name().to_utf8();
+            info.r#type() == r#type
+                && !info_name.contains("XTEST")
+                && name.is_none_or(|name| info_name == name)
+        })
+        .map(|info| info.device().id())
+        .collect();
+
+    Ok(devices)
+}
+
+fn get_synthetic_devices(devices: &HashMap<u16, String>) -> Vec<u16> {
+    // NOTE: The X server creates an XTest slave device for every master device,
+    // named like "Virtual core XTEST pointer"
+    devices
+        .iter()
+        .filter(|(_, name)| name.contains("XTEST"))
+        .map(|(&id, _)| id)
+        .collect()
+}
+
+pub fn query_pointer(conn: &Connection, window: x::Window) -> xcb::Result<(i16, i16)> {
+    let reply = conn.wait_for_reply(conn.send_request(&x::QueryPointer { window }))?;
+    Ok((reply.root_x(), reply.root_y()))
+}
+
+fn setup_xcb_events(conn: &Connection, window: x::Window, motion: bool) -> xcb::Result<()> {
     let device = xinput::Device::All;
-    let evmask = xinput::EventMaskBuf::new(
-        device,
-        &[XiEventMask::RAW_BUTTON_PRESS
-            | XiEventMask::RAW_BUTTON_RELEASE
-            | XiEventMask::KEY_PRESS
-            | XiEventMask::KEY_RELEASE],
-    );
+    let mut mask = XiEventMask::RAW_BUTTON_PRESS
+        | XiEventMask::RAW_BUTTON_RELEASE
+        | XiEventMask::KEY_PRESS
+        | XiEventMask::KEY_RELEASE;
+    if motion {
+        mask |= XiEventMask::RAW_MOTION;
+    }
+    let evmask = xinput::EventMaskBuf::new(device, &[mask]);
 
     conn.send_request(&xinput::XiSelectEvents {
         window,
@@ -105,74 +202,248 @@ impl InputSystem {
     pub fn try_init() -> Option<Self> {
         let conn = connect_xcb().ok()?;
         let window = get_root_window(&conn)?;
-        setup_xcb_events(&conn, window).ok()?;
+        setup_xcb_events(&conn, window, false).ok()?;
+        let devices = query_devices(&conn).ok()?;
+        let synthetic_devices = get_synthetic_devices(&devices);
+        let keymap = Keymap::query(&conn).ok()?;
         Some(Self {
             conn: Arc::new(conn),
             window,
+            devices,
+            synthetic_devices,
+            ignore_synthetic: true,
+            keymap: Arc::new(RwLock::new(keymap)),
+            held: Mutex::new(HashSet::new()),
         })
     }
 
+    // Also sends an event whenever the pointer moves, which is only needed
+    // while recording
+    pub fn listen_motion(&self) -> xcb::Result<()> {
+        setup_xcb_events(&self.conn, self.window, true)
+    }
+
+    pub fn pointer_position(&self) -> xcb::Result<(i16, i16)> {
+        query_pointer(&self.conn, self.window)
+    }
+
+    pub fn set_held(&self, input: HeldInput, is_held: bool) {
+        // NOTE: Also used while panicking, so a poisoned lock is still used
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        if is_held {
+            held.insert(input);
+        } else {
+            held.remove(&input);
+        }
+    }
+
+    // Forgets about every held input and returns them
+    pub fn take_held(&self) -> Vec<HeldInput> {
+        let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+        held.drain().collect()
+    }
+
+    // Takes a button away from the other applications whenever it's pressed
+    // along with exactly 'modifiers', until it's released. With a 'device',
+    // only on the devices with that name. Returns false if another application
+    // already grabbed it, or if there is no such device
+    pub fn grab_button(
+        &self,
+        button: InputButton,
+        modifiers: u32,
+        device: Option<&str>,
+    ) -> xcb::Result<bool> {
+        self.grab(
+            xinput::DeviceType::SlavePointer,
+            device,
+            xinput::GrabType::Button,
+            button.code(),
+            XiEventMask::BUTTON_PRESS | XiEventMask::BUTTON_RELEASE,
+            modifiers,
+        )
+    }
+
+    // Same as 'grab_button', but for a key
+    pub fn grab_key(
+        &self,
+        key: InputKey,
+        modifiers: u32,
+        device: Option<&str>,
+    ) -> xcb::Result<bool> {
+        self.grab(
+            xinput::DeviceType::SlaveKeyboard,
+            device,
+            xinput::GrabType::Keycode,
+            key as u32,
+            XiEventMask::KEY_PRESS | XiEventMask::KEY_RELEASE,
+            modifiers,
+        )
+    }
+
+    // NOTE: The physical devices are grabbed rather than their master. A
+    // grabbed slave device is detached from its master until the grab ends,
+    // so the press and the release both only reach this program, while the
+    // clicks and keys faked through the XTest device still reach the others.
+    // Devices plugged in after the start aren't grabbed
+    fn grab(
+        &self,
+        device_type: xinput::DeviceType,
+        device: Option<&str>,
+        grab_type: xinput::GrabType,
+        detail: u32,
+        mask: XiEventMask,
+        modifiers: u32,
+    ) -> xcb::Result<bool> {
+        // A detail of 0 would grab every key or button
+        assert_ne!(detail, 0, "[NC] Can't grab input 0");
+
+        let devices = query_slave_devices(&self.conn, device_type, device)?;
+        if devices.is_empty() {
+            return Ok(false);
+        }
+
+        // The grab also has to hold while Caps Lock or Num Lock are on
+        let lock = xlib::LockMask;
+        let num_lock = xlib::Mod2Mask;
+        let cookies: Vec<_> = devices
+            .into_iter()
+            .map(|id| {
+                self.conn.send_request(&xinput::XiPassiveGrabDevice {
+                    time: x::CURRENT_TIME,
+                    grab_window: self.window,
+                    cursor: x::Cursor::none(),
+                    detail,
+                    device: xinput::Device::from_id(id),
+                    grab_type,
+                    grab_mode: xinput::GrabMode22::Async,
+                    paired_device_mode: x::GrabMode::Async,
+                    owner_events: xinput::GrabOwner::NoOwner,
+                    mask: &[mask.bits()],
+                    modifiers: &[
+                        modifiers,
+                        modifiers | lock,
+                        modifiers | num_lock,
+                        modifiers | lock | num_lock,
+                    ],
+                })
+            })
+            .collect();
+
+        // The replies list the modifier combinations that couldn't be grabbed
+        let mut is_grabbed = true;
+        for cookie in cookies {
+            is_grabbed &= self.conn.wait_for_reply(cookie)?.modifiers().is_empty();
+        }
+        Ok(is_grabbed)
+    }
+
     pub fn spawn_event_loop(
         &self,
-        event_handler: impl Fn(InputEvent) -> bool + Sync + Send + 'static,
+        event_handler: impl Fn(InputEvent, &str) -> bool + Sync + Send + 'static,
     ) -> thread::JoinHandle<()> {
         let conn = self.conn.clone();
+        let devices = self.devices.clone();
+        let keymap = self.keymap.clone();
+        let ignored_devices = if self.ignore_synthetic {
+            self.synthetic_devices.clone()
+        } else {
+            vec![]
+        };
         thread::spawn(move || {
-            event_loop(conn, event_handler).unwrap();
+            event_loop(conn, devices, ignored_devices, keymap, event_handler).unwrap();
         })
     }
 }
 
 fn event_loop(
     conn: Arc<Connection>,
-    event_handler: impl Fn(InputEvent) -> bool,
+    mut devices: HashMap<u16, String>,
+    ignored_devices: Vec<u16>,
+    keymap: Arc<RwLock<Keymap>>,
+    event_handler: impl Fn(InputEvent, &str) -> bool,
 ) -> xcb::Result<()> {
     loop {
-        let ev = match conn.poll_for_event()? {
-            Some(e) => e,
-            None => {
-                thread::sleep(Duration::from_millis(10));
-                continue;
-            }
-        };
-        let input_event: InputEvent;
-
-        // TODO: Fix doubled button events
-        match ev {
+        // NOTE: Blocks until the next event, so the thread doesn't use any CPU
+        // while idle. Requests can still be sent from other threads meanwhile
+        let ev = conn.wait_for_event()?;
+        // NOTE: Selecting on 'Device::All' delivers every event twice, once from
+        // the physical (slave) device and once from its master device. Only the
+        // former is kept, which is also the one that tells which device it was
+        let (input_event, source) = match ev {
             xcb::Event::Input(xinput::Event::RawButtonPress(evbtn)) => {
-                let button = match InputButton::try_from(evbtn.detail() as u32) {
-                    Ok(b) => b,
-                    Err(_) => continue,
-                };
+                let source = evbtn.source().id();
+                if source != evbtn.device().id() || ignored_devices.contains(&source) {
+                    continue;
+                }
+
+                let button = InputButton::from(evbtn.detail());
 
-                input_event = InputEvent::ButtonPress(button);
+                (InputEvent::ButtonPress(button), source)
             }
 
             xcb::Event::Input(xinput::Event::RawButtonRelease(evbtn)) => {
-                let button = match InputButton::try_from(evbtn.detail()) {
-                    Ok(b) => b,
-                    Err(_) => continue,
-                };
+                let source = evbtn.source().id();
+                if source != evbtn.device().id() || ignored_devices.contains(&source) {
+                    continue;
+                }
+
+                let button = InputButton::from(evbtn.detail());
+
+                (InputEvent::ButtonRelease(button), source)
+            }
+
+            xcb::Event::Input(xinput::Event::RawMotion(evmotion)) => {
+                let source = evmotion.source().id();
+                if source != evmotion.device().id() || ignored_devices.contains(&source) {
+                    continue;
+                }
 
-                input_event = InputEvent::ButtonRelease(button);
+                (InputEvent::Motion, source)
             }
 
+            // Sent to every client, without having to select it
+            xcb::Event::X(x::Event::MappingNotify(evmap)) => {
+                if evmap.request() == x::Mapping::Keyboard {
+                    *keymap.write().unwrap() = Keymap::query(&conn)?;
+                }
+                continue;
+            }
+
+            // NOTE: The presses and releases of the keys grabbed by 'grab_key'
+            // also come from the slave device, while it's detached from its
+            // master
             xcb::Event::Input(xinput::Event::KeyPress(evkey)) => {
+                let source = evkey.source().id();
+                if source != evkey.device().id() || ignored_devices.contains(&source) {
+                    continue;
+                }
+
                 let key = evkey.detail() as InputKey;
 
-                input_event = InputEvent::KeyPress(key);
+                (InputEvent::KeyPress(key), source)
             }
 
             xcb::Event::Input(xinput::Event::KeyRelease(evkey)) => {
+                let source = evkey.source().id();
+                if source != evkey.device().id() || ignored_devices.contains(&source) {
+                    continue;
+                }
+
                 let key = evkey.detail() as InputKey;
 
-                input_event = InputEvent::KeyRelease(key);
+                (InputEvent::KeyRelease(key), source)
             }
 
             _ => continue,
+        };
+
+        // Devices plugged in after the start are looked up when first seen
+        if !devices.contains_key(&source) {
+            devices = query_devices(&conn)?;
         }
+        let device = devices.get(&source).map(String::as_str).unwrap_or("");
 
-        if !event_handler(input_event) {
+        if !event_handler(input_event, device) {
             break;
         }
     }
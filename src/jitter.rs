@@ -0,0 +1,107 @@
+use crate::settings;
+use rand::Rng;
+use rand_distr::Exp;
+use std::time::{Duration, Instant};
+
+// Small random pointer motions made while a command clicks
+#[derive(Debug)]
+pub struct Jitter {
+    amplitude: f64,
+    drift: f64,
+    interval: Exp<f64>,
+    offset: (f64, f64),
+    pub next_move: Option<Instant>,
+}
+
+impl Jitter {
+    pub fn parse(jitter: &settings::Jitter) -> Option<Self> {
+        // NOTE: The checks are written so that NaN fails them too
+        let is_valid = (0.0..f64::INFINITY).contains(&jitter.amplitude)
+            && (0.0..=1.0).contains(&jitter.drift)
+            && jitter.frequency > 0.0
+            && jitter.frequency.is_finite();
+        if !is_valid {
+            return None;
+        }
+
+        Some(Self {
+            amplitude: jitter.amplitude,
+            drift: jitter.drift,
+            // Motions happen at random times, 'frequency' times per second on average
+            interval: Exp::new(jitter.frequency).ok()?,
+            offset: (0.0, 0.0),
+            next_move: None,
+        })
+    }
+
+    // Makes the current pointer position the new origin
+    pub fn reset(&mut self) {
+        self.offset = (0.0, 0.0);
+        self.next_move = None;
+    }
+
+    // Returns the relative motion that is due at 'now', if any, and schedules
+    // the next one. Every motion is pulled back towards the origin by 'drift'
+    pub fn step<R: Rng + ?Sized>(&mut self, now: Instant, rng: &mut R) -> Option<(i16, i16)> {
+        if self.next_move.is_some_and(|next| now < next) {
+            return None;
+        }
+
+        let secs: f64 = rng.sample(self.interval);
+        self.next_move = Some(now + Duration::from_secs_f64(secs));
+
+        let dx =
+            (rng.gen_range(-self.amplitude..=self.amplitude) - self.offset.0 * self.drift).round();
+        let dy =
+            (rng.gen_range(-self.amplitude..=self.amplitude) - self.offset.1 * self.drift).round();
+        self.offset.0 += dx;
+        self.offset.1 += dy;
+
+        if dx == 0.0 && dy == 0.0 {
+            return None;
+        }
+        Some((dx as i16, dy as i16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn jitter(amplitude: f64, frequency: f64, drift: f64) -> Option<Jitter> {
+        Jitter::parse(&settings::Jitter {
+            amplitude,
+            frequency,
+            drift,
+        })
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(jitter(1.5, 0.0, 0.3).is_none());
+        assert!(jitter(1.5, -20.0, 0.3).is_none());
+        assert!(jitter(1.5, f64::INFINITY, 0.3).is_none());
+        assert!(jitter(1.5, f64::NAN, 0.3).is_none());
+        assert!(jitter(-1.0, 20.0, 0.3).is_none());
+        assert!(jitter(f64::NAN, 20.0, 0.3).is_none());
+        assert!(jitter(f64::INFINITY, 20.0, 0.3).is_none());
+        assert!(jitter(1.5, 20.0, 1.5).is_none());
+        assert!(jitter(1.5, 20.0, f64::NAN).is_none());
+        assert!(jitter(0.0, 20.0, 0.0).is_some());
+    }
+
+    #[test]
+    fn motions_stay_within_the_amplitude() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut j = jitter(2.0, 20.0, 0.0).unwrap();
+        let mut now = Instant::now();
+        for _ in 0..1000 {
+            if let Some((dx, dy)) = j.step(now, &mut rng) {
+                assert!(dx.abs() <= 2 && dy.abs() <= 2);
+            }
+            now = j.next_move.unwrap();
+        }
+    }
+}
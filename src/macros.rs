@@ -0,0 +1,607 @@
+use crate::clicker::{input_button_name, parse_input_button, ClickerInput};
+use crate::convert::string_to_keysym;
+use crate::distribution::RangeSampler;
+use crate::fakekeyboard;
+use crate::fakemouse;
+use crate::inputsys::{query_pointer, HeldInput, InputButton, InputEvent, InputKey, InputSystem};
+use crate::settings::{self, StepType};
+use crate::time::millis_to_duration;
+use crate::trajectory::Trajectory;
+use crate::window::WindowTarget;
+use rand::Rng;
+use rand_distr::Normal;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use xcb::x;
+
+// How often a macro checks whether its window is mapped again
+const WINDOW_RETRY: Duration = Duration::from_millis(250);
+
+// The shortest wait between two steps. A wait of 0 (e.g. 'Wait 0', or a short
+// one sped up) would run the macro again right away, in a busy loop
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+// Pointer motion is recorded at most this often, so that moving the pointer
+// doesn't turn into thousands of tiny steps
+const MOTION_INTERVAL: Duration = Duration::from_millis(15);
+
+#[derive(Debug)]
+enum Step {
+    KeyDown(x::Keysym),
+    KeyUp(x::Keysym),
+    KeyClick(x::Keysym),
+    ButtonDown(InputButton),
+    ButtonUp(InputButton),
+    ButtonClick(InputButton),
+    // Milliseconds until the next step
+    Wait(RangeSampler),
+    Move(i16, i16),
+    MoveTo(i16, i16),
+    MoveBy(i16, i16),
+}
+
+// A sequence of steps that runs on the clicker thread, one stretch between
+// two waits at a time
+#[derive(Debug)]
+pub struct Macro {
+    steps: Vec<Step>,
+    position: usize,
+    // Inputs pressed by the macro and not released yet
+    held: Vec<HeldInput>,
+    // The keycodes that the held keys were pressed with, which stay the same
+    // if the keyboard mapping changes meanwhile
+    pressed_keys: HashMap<x::Keysym, InputKey>,
+    // The path of a move step that is still being followed
+    trajectory: Trajectory,
+    // Divides the duration of every wait
+    speed: f64,
+    // Factor that every wait is multiplied with, centered around 1
+    humanize: Option<Normal<f64>>,
+}
+
+impl Macro {
+    pub fn parse(r#macro: &settings::Macro) -> Option<Self> {
+        let steps = match (&r#macro.steps, &r#macro.file) {
+            (Some(steps), None) => parse_steps(steps)?,
+            (None, Some(path)) => parse_steps(&load(path)?)?,
+            _ => return None,
+        };
+
+        if steps.is_empty() {
+            return None;
+        }
+
+        let speed = r#macro.speed.unwrap_or(1.0);
+        // NOTE: Written so that NaN fails the check too
+        if !(speed > 0.0 && speed.is_finite()) {
+            return None;
+        }
+
+        let humanize = match r#macro.humanize {
+            Some(stddev) => Some(Normal::new(1.0, stddev).ok()?),
+            None => None,
+        };
+
+        Some(Self {
+            steps,
+            position: 0,
+            held: vec![],
+            pressed_keys: HashMap::new(),
+            trajectory: Trajectory::default(),
+            speed,
+            humanize,
+        })
+    }
+
+    // Whether the macro can be repeated without flooding the X server
+    pub fn has_wait(&self) -> bool {
+        self.steps.iter().any(|step| matches!(step, Step::Wait(_)))
+    }
+
+    // Runs the steps up to the next wait and returns its duration. Once the last
+    // step ran, the macro starts over if 'repeat' is set, otherwise it returns
+    // None and is ready to run again from the start. The positions are relative
+    // to 'window' if there is one, and the macro pauses while it isn't mapped
+    pub fn run<R: Rng + ?Sized>(
+        &mut self,
+        sys: &InputSystem,
+        rng: &mut R,
+        repeat: bool,
+        window: Option<&mut WindowTarget>,
+    ) -> xcb::Result<Option<Duration>> {
+        let (origin_x, origin_y) = match window {
+            Some(w) => match w.origin(sys)? {
+                Some(origin) => origin,
+                None => return Ok(Some(WINDOW_RETRY)),
+            },
+            None => (0, 0),
+        };
+
+        loop {
+            // A move step only ends once its path was followed
+            if let Some(wait) = self.trajectory.advance(sys)? {
+                if !wait.is_zero() {
+                    return Ok(Some(wait.div_f64(self.speed)));
+                }
+                continue;
+            }
+
+            if self.position == self.steps.len() {
+                self.position = 0;
+                if !repeat {
+                    return Ok(None);
+                }
+            }
+
+            let step = &self.steps[self.position];
+            self.position += 1;
+            match step {
+                // The keys are looked up when they're sent, in case the
+                // keyboard mapping changed
+                Step::KeyDown(keysym) => {
+                    if let Some(k) = fakekeyboard::keycode(sys, *keysym) {
+                        fakekeyboard::press(sys, k)?;
+                        self.held.push(HeldInput::Key(k));
+                        self.pressed_keys.insert(*keysym, k);
+                    }
+                }
+
+                Step::KeyUp(keysym) => {
+                    let keycode = match self.pressed_keys.remove(keysym) {
+                        Some(k) => Some(k),
+                        None => fakekeyboard::keycode(sys, *keysym),
+                    };
+                    if let Some(k) = keycode {
+                        fakekeyboard::release(sys, k)?;
+                        self.held.retain(|input| *input != HeldInput::Key(k));
+                    }
+                }
+
+                Step::KeyClick(keysym) => {
+                    if let Some(k) = fakekeyboard::keycode(sys, *keysym) {
+                        fakekeyboard::click(sys, k)?;
+                    }
+                }
+
+                Step::ButtonDown(b) => {
+                    fakemouse::press(sys, b)?;
+                    self.held.push(HeldInput::Button(*b));
+                }
+
+                Step::ButtonUp(b) => {
+                    fakemouse::release(sys, b)?;
+                    self.held.retain(|input| *input != HeldInput::Button(*b));
+                }
+
+                Step::ButtonClick(b) => fakemouse::click(sys, b)?,
+
+                Step::Wait(wait) => return Ok(Some(self.sample_wait(wait, rng))),
+
+                Step::Move(dx, dy) => fakemouse::move_relative(sys, *dx, *dy)?,
+
+                Step::MoveTo(x, y) => {
+                    let from = sys.pointer_position()?;
+                    let to = (origin_x.saturating_add(*x), origin_y.saturating_add(*y));
+                    self.trajectory = Trajectory::generate(from, to, rng);
+                }
+
+                Step::MoveBy(dx, dy) => {
+                    let from = sys.pointer_position()?;
+                    let to = (from.0.saturating_add(*dx), from.1.saturating_add(*dy));
+                    self.trajectory = Trajectory::generate(from, to, rng);
+                }
+            }
+        }
+    }
+
+    fn sample_wait<R: Rng + ?Sized>(&self, wait: &RangeSampler, rng: &mut R) -> Duration {
+        let mut ms = wait.sample(rng) / self.speed;
+        if let Some(humanize) = &self.humanize {
+            ms *= rng.sample(humanize).max(0.0);
+        }
+        millis_to_duration(ms).max(MIN_WAIT)
+    }
+
+    // Interrupts the macro, releasing whatever it still holds down
+    pub fn stop(&mut self, sys: &InputSystem) -> xcb::Result<()> {
+        self.position = 0;
+        self.trajectory.clear();
+        self.pressed_keys.clear();
+        for input in self.held.drain(..) {
+            match input {
+                HeldInput::Key(k) => fakekeyboard::release(sys, k)?,
+                HeldInput::Button(b) => fakemouse::release(sys, &b)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads the steps of a macro file, a JSON array of steps
+pub fn load(path: &str) -> Option<Vec<settings::Step>> {
+    let file = File::open(path).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+pub fn save(steps: &[settings::Step], path: &str) -> io::Result<()> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(file, steps)?;
+    Ok(())
+}
+
+fn new_step(r#type: StepType) -> settings::Step {
+    settings::Step {
+        r#type,
+        value: None,
+        duration: None,
+        min: None,
+        max: None,
+        x: None,
+        y: None,
+    }
+}
+
+fn input_step(input: &ClickerInput, is_press: bool) -> settings::Step {
+    let (r#type, value) = match (input, is_press) {
+        (ClickerInput::Key(k), true) => (StepType::KeyDown, k.clone()),
+        (ClickerInput::Key(k), false) => (StepType::KeyUp, k.clone()),
+        (ClickerInput::Button(b), true) => (StepType::ButtonDown, input_button_name(b)),
+        (ClickerInput::Button(b), false) => (StepType::ButtonUp, input_button_name(b)),
+    };
+    settings::Step {
+        value: Some(value),
+        ..new_step(r#type)
+    }
+}
+
+struct Recorder {
+    steps: Vec<settings::Step>,
+    last_step: Option<Instant>,
+    // When the motion was last checked for, whether or not a move was recorded
+    motion_checked: Option<Instant>,
+    // Position of the pointer after the last recorded move
+    pointer: (i16, i16),
+    // Inputs pressed during the recording and not released yet
+    pressed: HashSet<ClickerInput>,
+}
+
+impl Recorder {
+    fn new(pointer: (i16, i16)) -> Self {
+        Self {
+            steps: vec![],
+            last_step: None,
+            motion_checked: None,
+            pointer,
+            pressed: HashSet::new(),
+        }
+    }
+
+    // Adds a step, waiting for as long as it took since the previous one
+    fn push(&mut self, now: Instant, step: settings::Step) {
+        if let Some(last) = self.last_step {
+            let duration = (now - last).as_millis() as u32;
+            if duration > 0 {
+                self.steps.push(settings::Step {
+                    duration: Some(duration),
+                    ..new_step(StepType::Wait)
+                });
+            }
+        }
+
+        self.steps.push(step);
+        self.last_step = Some(now);
+    }
+
+    // Records the motion since the last recorded move, if there was any
+    fn flush_motion(&mut self, now: Instant, position: (i16, i16)) {
+        self.motion_checked = Some(now);
+        let (x, y) = position;
+        let (last_x, last_y) = self.pointer;
+        if (x, y) == (last_x, last_y) {
+            return;
+        }
+
+        self.push(
+            now,
+            settings::Step {
+                x: Some(x.wrapping_sub(last_x)),
+                y: Some(y.wrapping_sub(last_y)),
+                ..new_step(StepType::Move)
+            },
+        );
+        self.pointer = (x, y);
+    }
+
+    // Whether pointer motion at 'now' would be recorded. The position of the
+    // pointer takes a round trip to the X server, so it's only queried then
+    fn is_motion_due(&self, now: Instant) -> bool {
+        self.motion_checked
+            .is_none_or(|checked| now - checked >= MOTION_INTERVAL)
+    }
+
+    fn press(&mut self, now: Instant, input: ClickerInput, position: (i16, i16)) {
+        // Repeated press events without a release in between are ignored
+        if self.pressed.contains(&input) {
+            return;
+        }
+
+        // The click lands where the pointer is now, not where it last was
+        self.flush_motion(now, position);
+        self.push(now, input_step(&input, true));
+        self.pressed.insert(input);
+    }
+
+    fn release(&mut self, now: Instant, input: ClickerInput, position: (i16, i16)) {
+        // Inputs that were already held when the recording started are left
+        // out, the macro never pressed them
+        if !self.pressed.remove(&input) {
+            return;
+        }
+
+        self.flush_motion(now, position);
+        self.push(now, input_step(&input, false));
+    }
+
+    // The recorded steps, releasing whatever is still held so that replaying
+    // them doesn't leave inputs stuck
+    fn finish(mut self) -> Vec<settings::Step> {
+        let now = self.last_step.unwrap_or_else(Instant::now);
+        for input in std::mem::take(&mut self.pressed) {
+            self.push(now, input_step(&input, false));
+        }
+        self.steps
+    }
+}
+
+// Captures the keys, buttons and pointer motion until 'stop' is pressed. The
+// stop key itself isn't recorded
+pub fn record(sys: &InputSystem, stop: String) -> xcb::Result<Vec<settings::Step>> {
+    sys.listen_motion()?;
+    let recorder = Arc::new(Mutex::new(Recorder::new(sys.pointer_position()?)));
+
+    let event_loop = {
+        let recorder = recorder.clone();
+        let keymap = sys.keymap.clone();
+        let (conn, window) = (sys.conn.clone(), sys.window);
+        sys.spawn_event_loop(move |ev, _device| {
+            let now = Instant::now();
+            let keycode_to_string = |key| keymap.read().unwrap().keycode_to_string(key);
+            let pointer = || query_pointer(&conn, window).expect("[NC] Failed to query pointer");
+
+            let (input, is_press) = match ev {
+                InputEvent::ButtonPress(btn) => (ClickerInput::Button(btn), true),
+                InputEvent::ButtonRelease(btn) => (ClickerInput::Button(btn), false),
+                InputEvent::KeyPress(key) => (ClickerInput::Key(keycode_to_string(key)), true),
+                InputEvent::KeyRelease(key) => (ClickerInput::Key(keycode_to_string(key)), false),
+                InputEvent::Motion => {
+                    let mut recorder = recorder.lock().unwrap();
+                    if recorder.is_motion_due(now) {
+                        recorder.flush_motion(now, pointer());
+                    }
+                    return true;
+                }
+            };
+
+            if input == ClickerInput::Key(stop.clone()) {
+                return !is_press;
+            }
+
+            let position = pointer();
+            let mut recorder = recorder.lock().unwrap();
+            if is_press {
+                recorder.press(now, input, position);
+            } else {
+                recorder.release(now, input, position);
+            }
+
+            true
+        })
+    };
+
+    event_loop.join().expect("[NC] Event loop panicked");
+    let recorder = Arc::into_inner(recorder).expect("[NC] Recorder is still in use");
+    Ok(recorder.into_inner().unwrap().finish())
+}
+
+fn parse_steps(steps: &[settings::Step]) -> Option<Vec<Step>> {
+    let mut parsed = vec![];
+    for step in steps {
+        // A button step with a position moves there first
+        if let (
+            StepType::ButtonDown | StepType::ButtonUp | StepType::ButtonClick,
+            Some(x),
+            Some(y),
+        ) = (&step.r#type, step.x, step.y)
+        {
+            parsed.push(Step::MoveTo(x, y));
+        }
+        parsed.push(parse_step(step)?);
+    }
+    Some(parsed)
+}
+
+fn parse_step(step: &settings::Step) -> Option<Step> {
+    let key = || string_to_keysym(step.value.as_ref()?);
+    let button = || {
+        // The position needs both coordinates
+        if step.x.is_some() != step.y.is_some() {
+            return None;
+        }
+        parse_input_button(step.value.clone()?)
+    };
+
+    match step.r#type {
+        StepType::KeyDown => Some(Step::KeyDown(key()?)),
+        StepType::KeyUp => Some(Step::KeyUp(key()?)),
+        StepType::KeyClick => Some(Step::KeyClick(key()?)),
+        StepType::ButtonDown => Some(Step::ButtonDown(button()?)),
+        StepType::ButtonUp => Some(Step::ButtonUp(button()?)),
+        StepType::ButtonClick => Some(Step::ButtonClick(button()?)),
+        StepType::Wait => {
+            let (min, max) = match (step.duration, step.min, step.max) {
+                (Some(d), None, None) => (d, d),
+                (None, Some(min), Some(max)) if min <= max => (min, max),
+                _ => return None,
+            };
+            Some(Step::Wait(RangeSampler::parse(
+                min as f64..=max as f64,
+                None,
+            )?))
+        }
+        StepType::Move => Some(Step::Move(step.x.unwrap_or(0), step.y.unwrap_or(0))),
+        StepType::MoveTo => Some(Step::MoveTo(step.x?, step.y?)),
+        StepType::MoveBy => Some(Step::MoveBy(step.x.unwrap_or(0), step.y.unwrap_or(0))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn step(r#type: StepType, value: &str) -> settings::Step {
+        settings::Step {
+            value: Some(value.to_string()),
+            ..new_step(r#type)
+        }
+    }
+
+    fn wait(duration: Option<u32>, min: Option<u32>, max: Option<u32>) -> settings::Step {
+        settings::Step {
+            duration,
+            min,
+            max,
+            ..new_step(StepType::Wait)
+        }
+    }
+
+    fn button_at(x: Option<i16>, y: Option<i16>) -> settings::Step {
+        settings::Step {
+            x,
+            y,
+            ..step(StepType::ButtonClick, "L")
+        }
+    }
+
+    fn r#macro(steps: Vec<settings::Step>, speed: f64) -> Macro {
+        Macro::parse(&settings::Macro {
+            steps: Some(steps),
+            file: None,
+            speed: Some(speed),
+            humanize: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn buttons_with_a_position_move_there_first() {
+        let steps = parse_steps(&[button_at(Some(10), Some(20)), button_at(None, None)]).unwrap();
+        assert!(matches!(
+            steps.as_slice(),
+            [
+                Step::MoveTo(10, 20),
+                Step::ButtonClick(InputButton::Left),
+                Step::ButtonClick(InputButton::Left),
+            ]
+        ));
+
+        // The position needs both coordinates
+        assert!(parse_steps(&[button_at(Some(10), None)]).is_none());
+    }
+
+    #[test]
+    fn waits_need_a_duration_or_a_range() {
+        assert!(parse_steps(&[wait(Some(50), None, None)]).is_some());
+        assert!(parse_steps(&[wait(None, Some(20), Some(80))]).is_some());
+        assert!(parse_steps(&[wait(None, Some(80), Some(20))]).is_none());
+        assert!(parse_steps(&[wait(Some(50), Some(20), Some(80))]).is_none());
+        assert!(parse_steps(&[wait(None, None, None)]).is_none());
+    }
+
+    #[test]
+    fn waits_never_round_down_to_zero() {
+        let mut rng = StdRng::seed_from_u64(5);
+
+        let zero = r#macro(vec![wait(Some(0), None, None)], 1.0);
+        let Step::Wait(w) = &zero.steps[0] else {
+            unreachable!()
+        };
+        assert_eq!(zero.sample_wait(w, &mut rng), MIN_WAIT);
+
+        let sped_up = r#macro(vec![wait(Some(1), None, None)], 1000.0);
+        let Step::Wait(w) = &sped_up.steps[0] else {
+            unreachable!()
+        };
+        assert_eq!(sped_up.sample_wait(w, &mut rng), MIN_WAIT);
+    }
+
+    #[test]
+    fn rejects_non_positive_and_non_finite_speeds() {
+        let parses = |speed| {
+            Macro::parse(&settings::Macro {
+                steps: Some(vec![wait(Some(50), None, None)]),
+                file: None,
+                speed: Some(speed),
+                humanize: None,
+            })
+            .is_some()
+        };
+        assert!(parses(2.0));
+        assert!(!parses(0.0));
+        assert!(!parses(-1.0));
+        assert!(!parses(f64::NAN));
+        assert!(!parses(f64::INFINITY));
+    }
+
+    #[test]
+    fn recorded_motion_is_throttled() {
+        let start = Instant::now();
+        let mut recorder = Recorder::new((0, 0));
+        assert!(recorder.is_motion_due(start));
+
+        recorder.flush_motion(start, (5, -5));
+        assert!(!recorder.is_motion_due(start + MOTION_INTERVAL / 2));
+        assert!(recorder.is_motion_due(start + MOTION_INTERVAL));
+
+        // A press records the motion that was left out in the meantime first
+        let button = ClickerInput::Button(InputButton::Left);
+        recorder.press(start + MOTION_INTERVAL / 2, button, (8, -5));
+
+        let steps = recorder.finish();
+        let types: Vec<_> = steps.iter().map(|s| &s.r#type).collect();
+        assert!(matches!(
+            types.as_slice(),
+            [
+                StepType::Move,
+                StepType::Wait,
+                StepType::Move,
+                StepType::ButtonDown,
+                StepType::ButtonUp,
+            ]
+        ));
+        assert_eq!((steps[2].x, steps[2].y), (Some(3), Some(0)));
+    }
+
+    #[test]
+    fn motion_checks_without_a_move_are_throttled_too() {
+        let start = Instant::now();
+        let mut recorder = Recorder::new((0, 0));
+
+        // The pointer came back to where it was last recorded
+        recorder.flush_motion(start, (0, 0));
+        assert!(!recorder.is_motion_due(start + MOTION_INTERVAL / 2));
+        assert!(recorder.is_motion_due(start + MOTION_INTERVAL));
+        assert!(recorder.finish().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(parse_steps(&[step(StepType::KeyClick, "Return")]).is_some());
+        assert!(parse_steps(&[step(StepType::KeyDown, "NoSuchKey")]).is_none());
+        assert!(parse_steps(&[new_step(StepType::KeyUp)]).is_none());
+    }
+}
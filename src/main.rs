@@ -1,175 +1,403 @@
+mod analyze;
+mod artifacts;
 mod clicker;
 mod convert;
+mod distribution;
 mod fakekeyboard;
 mod fakemouse;
+mod fatigue;
+mod generator;
 mod inputsys;
+mod jitter;
+mod macros;
+mod profile;
+mod ramp;
+mod scheduler;
 mod settings;
+mod shutdown;
+mod targets;
 mod time;
+mod trajectory;
+mod window;
 
-use clicker::{ClickerAction, ClickerInput, ClickerState};
-use convert::{keycode_to_string, string_to_keycode};
-use env_logger;
+use clicker::{ClickerAction, ClickerCommand, ClickerInput, ClickerState};
 use inputsys::{InputEvent, InputSystem};
 use log::info;
-use rand::{self, Rng};
-use settings::{Method, Settings};
+use scheduler::Scheduler;
+use settings::{Input, InputType, Method, Settings};
 use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
-use time::{cps_to_millis, get_timestamp};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, process, thread};
+use targets::Aim;
 
-fn event_handler<'a>(
+fn event_handler(
     ev: InputEvent,
-    _sys: Arc<InputSystem>,
-    state: Arc<Mutex<ClickerState>>,
+    device: &str,
+    sys: Arc<InputSystem>,
+    scheduler: Arc<Scheduler>,
 ) -> bool {
-    match ev {
+    let keycode_to_string = |key| sys.keymap.read().unwrap().keycode_to_string(key);
+
+    let (key, is_press) = match ev {
         InputEvent::ButtonPress(btn) => {
-            info!("Button Press: {:?}", btn);
-            let key = ClickerInput::Button(btn);
-            let mut clicker_state = state.lock().unwrap();
-            let commands = &mut (*clicker_state).commands;
-            if let Some(cmd) = commands.get_mut(&key) {
-                if cmd.method == Method::Hold {
-                    cmd.is_active = true;
-                    info!("Updated state of '{:?}' to active", key);
-                }
-            }
+            info!("Button Press: {:?} ({})", btn, device);
+            (ClickerInput::Button(btn), true)
         }
 
         InputEvent::ButtonRelease(btn) => {
-            info!("Button Release: {:?}", btn);
-            let key = ClickerInput::Button(btn);
-            let mut clicker_state = state.lock().unwrap();
-            let commands = &mut (*clicker_state).commands;
-            if let Some(cmd) = commands.get_mut(&key) {
-                match cmd.method {
-                    Method::Hold => {
-                        cmd.is_active = false;
-                        info!("Updated state of '{:?}' to inactive", key);
-                    }
-
-                    Method::Toggle => {
-                        cmd.is_active = !cmd.is_active;
-                        info!(
-                            "Update state of '{:?}' to {}active",
-                            key,
-                            if !cmd.is_active { "in" } else { "" }
-                        )
-                    }
-                }
-            }
+            info!("Button Release: {:?} ({})", btn, device);
+            (ClickerInput::Button(btn), false)
         }
 
         InputEvent::KeyPress(key) => {
             let keystring = keycode_to_string(key);
-            info!("Key Press: {:?}", keystring);
-            let key = ClickerInput::Key(keystring);
-            let mut clicker_state = state.lock().unwrap();
-            let commands = &mut (*clicker_state).commands;
-            if let Some(cmd) = commands.get_mut(&key) {
-                if cmd.method == Method::Hold {
-                    cmd.is_active = true;
-                    info!("Updated state of '{:?}' to active", key);
-                }
-            }
+            info!("Key Press: {:?} ({})", keystring, device);
+            (ClickerInput::Key(keystring), true)
         }
 
         InputEvent::KeyRelease(key) => {
             let keystring = keycode_to_string(key);
-            info!("Key Release: {:?}", keycode_to_string(key));
-            let key = ClickerInput::Key(keystring);
-            let mut clicker_state = state.lock().unwrap();
-            let commands = &mut (*clicker_state).commands;
-            if let Some(cmd) = commands.get_mut(&key) {
-                match cmd.method {
-                    Method::Hold => {
-                        cmd.is_active = false;
-                        info!("Updated state of '{:?}' to inactive", key);
-                    }
+            info!("Key Release: {:?} ({})", keystring, device);
+            (ClickerInput::Key(keystring), false)
+        }
 
-                    Method::Toggle => {
-                        info!("toggle");
-                        cmd.is_active = !cmd.is_active;
-                        info!(
-                            "Update state of '{:?}' to {}active",
-                            key,
-                            if !cmd.is_active { "in" } else { "" }
-                        )
-                    }
+        // Not listened to
+        InputEvent::Motion => return true,
+    };
+
+    {
+        let mut clicker_state = scheduler.lock();
+        if is_press {
+            if let Some(cmd) = clicker_state.press(&key, device) {
+                let was_active = cmd.is_active;
+                cmd.trigger(Instant::now());
+                if cmd.is_active != was_active {
+                    info!(
+                        "Updated state of '{:?}' to {}active",
+                        cmd.listen,
+                        if !cmd.is_active { "in" } else { "" }
+                    );
+                }
+            }
+        } else {
+            // Releasing any input of a chord ends it
+            for cmd in clicker_state.release(&key) {
+                let was_active = cmd.is_active;
+                cmd.untrigger(Instant::now());
+                if cmd.is_active != was_active {
+                    info!("Updated state of '{:?}' to inactive", cmd.listen);
                 }
             }
         }
     }
 
-    return true;
+    scheduler.notify();
+    true
 }
 
-fn clicker_thread(sys: Arc<InputSystem>, state: Arc<Mutex<ClickerState>>, delay_ms: u64) {
-    let mut rng = rand::thread_rng();
+fn clicker_thread(sys: Arc<InputSystem>, scheduler: Arc<Scheduler>) {
+    let mut clicker_state = scheduler.lock();
     loop {
-        {
-            let now = get_timestamp();
-            let mut clicker_state = state.lock().unwrap();
-            for (_, cmd) in &mut clicker_state.commands {
-                if !cmd.is_active {
-                    if cmd.is_pressed {
-                        (*cmd).is_pressed = false;
-                        match &cmd.action {
-                            ClickerAction::ButtonPress(b) => {
-                                fakemouse::release(&sys, b).unwrap();
-                            }
+        let now = Instant::now();
+        for cmd in clicker_state.commands.iter_mut() {
+            cmd.update(now);
 
-                            ClickerAction::KeyPress(k) => {
-                                let keycode = string_to_keycode(k);
-                                fakekeyboard::release(&sys, keycode).unwrap();
-                            }
+            if let Some(stop_at) = cmd.stop_at() {
+                if now >= stop_at {
+                    // Any cool-down already happened before the stop
+                    cmd.is_active = false;
+                    info!("Stopped timed command after {:?}", cmd.stop_after.unwrap());
+                }
+            }
 
-                            _ => {
-                                panic!("[NC] This block should never hit");
+            if let Some(t) = &mut cmd.targets {
+                t.follow(&sys, now).unwrap();
+            }
+
+            if cmd.is_clicking(now) {
+                if let Some(j) = &mut cmd.jitter {
+                    if let Some((dx, dy)) = j.step(now, &mut cmd.rngs.jitter) {
+                        fakemouse::move_relative(&sys, dx, dy).unwrap();
+                    }
+                }
+            }
+
+            // A click that is still held down is released once its hold
+            // duration is over, even if the command was deactivated meanwhile
+            if let Some(release_at) = cmd.release_at {
+                if now >= release_at {
+                    cmd.release_at = None;
+                    match &mut cmd.action {
+                        ClickerAction::ButtonClick(b, _) => {
+                            fakemouse::release(&sys, b).unwrap();
+                            if let Some(t) = &mut cmd.targets {
+                                t.restore(&sys, &mut cmd.rngs.targets, now).unwrap();
                             }
                         }
+
+                        ClickerAction::KeyClick(k, _) => {
+                            k.release(&sys).unwrap();
+                        }
+
+                        _ => {
+                            panic!("[NC] This block should never hit");
+                        }
                     }
-                    continue;
+                }
+                continue;
+            }
+
+            if !cmd.is_clicking(now) {
+                if let ClickerAction::Macro(m) = &mut cmd.action {
+                    m.stop(&sys).unwrap();
+                    cmd.next_action = None;
+                }
+
+                if let Some(t) = &mut cmd.targets {
+                    t.cancel(&sys, &mut cmd.rngs.targets, now).unwrap();
                 }
 
                 if cmd.is_pressed {
-                    continue;
+                    cmd.is_pressed = false;
+                    match &mut cmd.action {
+                        ClickerAction::ButtonPress(b) => {
+                            fakemouse::release(&sys, b).unwrap();
+                        }
+
+                        ClickerAction::KeyPress(k) => {
+                            k.release(&sys).unwrap();
+                        }
+
+                        _ => {
+                            panic!("[NC] This block should never hit");
+                        }
+                    }
                 }
+                continue;
+            }
+
+            if cmd.is_pressed {
+                continue;
+            }
+
+            let deadline = match cmd.next_action {
+                Some(d) if d > now => continue,
+                Some(d) => d,
+                None => now,
+            };
+
+            // A macro runs up to its next wait, which is then scheduled like the
+            // interval between two clicks
+            let interval = if let ClickerAction::Macro(m) = &mut cmd.action {
+                let repeat = cmd.method != Method::Once;
+                match m
+                    .run(&sys, &mut cmd.rngs.r#macro, repeat, cmd.window.as_mut())
+                    .unwrap()
+                {
+                    Some(wait) => wait,
+                    None => {
+                        cmd.deactivate(now);
+                        cmd.next_action = None;
+                        continue;
+                    }
+                }
+            } else {
+                // A click at a position waits for the pointer to get there.
+                // Clicks aimed at a window that isn't mapped are skipped
+                let is_aimed = match &mut cmd.targets {
+                    Some(t) => {
+                        match t
+                            .aim(&sys, &mut cmd.rngs.targets, cmd.window.as_mut(), now)
+                            .unwrap()
+                        {
+                            Aim::Moving(duration) => {
+                                cmd.next_action = Some(now + duration);
+                                continue;
+                            }
+                            Aim::Unmapped => false,
+                            Aim::OnTarget => true,
+                        }
+                    }
+                    None => true,
+                };
+
+                let click = cmd.next_click(now);
+                let skip = click.as_ref().is_some_and(|c| c.skip);
+                let hold = click.as_ref().and_then(|c| c.hold);
+
+                match &mut cmd.action {
+                    // A one-shot press is a single click
+                    ClickerAction::ButtonPress(b) if cmd.method == Method::Once => {
+                        fakemouse::click(&sys, b).unwrap();
+                        cmd.deactivate(now);
+                        continue;
+                    }
 
-                match &cmd.action {
                     ClickerAction::ButtonPress(b) => {
                         fakemouse::press(&sys, b).unwrap();
-                        (*cmd).is_pressed = true;
+                        cmd.is_pressed = true;
+                        continue;
                     }
 
-                    ClickerAction::ButtonClick(b, r) => {
-                        if now - cmd.last_action > cps_to_millis(cmd.next_cps.unwrap()) {
-                            fakemouse::click(&sys, b).unwrap();
-                            (*cmd).next_cps = Some(rng.gen_range(r.clone()));
-                            (*cmd).last_action = now;
+                    ClickerAction::ButtonClick(b, _) => match hold {
+                        _ if !is_aimed => {}
+
+                        Some(h) => {
+                            fakemouse::press(&sys, b).unwrap();
+                            cmd.release_at = Some(now + h);
                         }
+
+                        // A missed click moved the pointer all the same
+                        None => {
+                            if !skip {
+                                fakemouse::click(&sys, b).unwrap();
+                            }
+                            if let Some(t) = &mut cmd.targets {
+                                t.restore(&sys, &mut cmd.rngs.targets, now).unwrap();
+                            }
+                        }
+                    },
+
+                    ClickerAction::KeyClick(..) if skip => {}
+
+                    ClickerAction::KeyPress(k) if cmd.method == Method::Once => {
+                        k.click(&sys).unwrap();
+                        cmd.deactivate(now);
+                        continue;
                     }
 
                     ClickerAction::KeyPress(k) => {
-                        let keycode = string_to_keycode(&k);
-                        fakekeyboard::press(&sys, keycode).unwrap();
-                        (*cmd).is_pressed = true;
+                        k.press(&sys).unwrap();
+                        cmd.is_pressed = true;
+                        continue;
                     }
 
-                    ClickerAction::KeyClick(k, r) => {
-                        if now - cmd.last_action > cps_to_millis(cmd.next_cps.unwrap()) {
-                            let keycode = string_to_keycode(&k);
-                            fakekeyboard::click(&sys, keycode).unwrap();
-                            (*cmd).next_cps = Some(rng.gen_range(r.clone()));
-                            (*cmd).last_action = now;
+                    ClickerAction::KeyClick(k, _) => match hold {
+                        Some(h) => {
+                            k.press(&sys).unwrap();
+                            cmd.release_at = Some(now + h);
                         }
+
+                        None => k.click(&sys).unwrap(),
+                    },
+
+                    ClickerAction::Macro(_) => {
+                        panic!("[NC] This block should never hit");
                     }
+                };
+
+                if cmd.method == Method::Once {
+                    cmd.deactivate(now);
+                    cmd.next_action = None;
+                    continue;
                 }
-            }
+
+                click.unwrap().interval
+            };
+
+            // The next click is scheduled from the previous deadline rather than
+            // from 'now', so that wakeup latency doesn't add up over time. If the
+            // deadline is too far behind (e.g. the command was just reactivated),
+            // the schedule starts over from 'now' instead of bursting to catch up
+            let base = if now - deadline < interval {
+                deadline
+            } else {
+                now
+            };
+            cmd.next_action = Some(base + interval);
         }
-        thread::sleep(Duration::from_millis(delay_ms));
+
+        let now = Instant::now();
+        let next_deadline = clicker_state
+            .commands
+            .iter()
+            .filter_map(|cmd| cmd.deadline(now))
+            .min();
+        clicker_state = scheduler.wait_until(clicker_state, next_deadline);
+    }
+}
+
+fn record_profile(args: &[String]) {
+    let [r#type, value, seconds, path] = args else {
+        eprintln!("Usage: natty-clicker record-profile <Key|Button> <value> <seconds> <output>");
+        process::exit(1);
+    };
+
+    let r#type = match r#type.as_str() {
+        "Key" => InputType::Key,
+        "Button" => InputType::Button,
+        _ => panic!("[NC] Invalid input type: {}", r#type),
+    };
+    let input = ClickerInput::parse(&Input {
+        r#type,
+        value: value.clone(),
+    })
+    .expect("[NC] Invalid input");
+    let seconds: u64 = seconds.parse().expect("[NC] Invalid amount of seconds");
+
+    let sys = InputSystem::try_init().expect("[NC] Failed to initialize input system");
+    info!("Recording {:?} for {} seconds...", input, seconds);
+    let profile = profile::record(&sys, input, Duration::from_secs(seconds));
+    info!(
+        "Recorded {} intervals and {} holds",
+        profile.intervals.len(),
+        profile.holds.len()
+    );
+
+    profile.save(path).expect("[NC] Failed to save profile");
+    info!("Saved profile to '{}'", path);
+}
+
+fn record_macro(args: &[String]) {
+    let (path, stop) = match args {
+        [path] => (path, "Escape"),
+        [path, stop] => (path, stop.as_str()),
+        _ => {
+            eprintln!("Usage: natty-clicker record <output> [stop key]");
+            process::exit(1);
+        }
+    };
+
+    let sys = InputSystem::try_init().expect("[NC] Failed to initialize input system");
+    info!("Recording a macro until {:?} is pressed...", stop);
+    let steps = macros::record(&sys, stop.to_string()).expect("[NC] Failed to record macro");
+    info!("Recorded {} steps", steps.len());
+
+    macros::save(&steps, path).expect("[NC] Failed to save macro");
+    info!("Saved macro to '{}'", path);
+}
+
+fn analyze(args: &[String]) {
+    let (index, clicks, csv) = match args {
+        [index] => (index, None, None),
+        [index, clicks] => (index, Some(clicks), None),
+        [index, flag, path] if flag == "--csv" => (index, None, Some(path)),
+        [index, clicks, flag, path] if flag == "--csv" => (index, Some(clicks), Some(path)),
+        _ => {
+            eprintln!("Usage: natty-clicker analyze <command> [clicks] [--csv <output>]");
+            process::exit(1);
+        }
+    };
+
+    let index: usize = index.parse().expect("[NC] Invalid command index");
+    let clicks: usize = match clicks {
+        Some(c) => c.parse().expect("[NC] Invalid amount of clicks"),
+        None => 1000,
+    };
+
+    let settings = Settings::load().expect("[NC] Failed to load settings");
+    let mut cmd = ClickerCommand::parse(&settings, index).expect("[NC] Failed to parse command");
+    if !cmd.action.is_click() {
+        panic!("[NC] Command {} doesn't click", index);
+    }
+
+    let intervals = analyze::simulate(&mut cmd, clicks);
+    analyze::print_report(&intervals);
+
+    if let Some(path) = csv {
+        analyze::save_csv(&intervals, path).expect("[NC] Failed to save CSV");
+        info!("Saved intervals to '{}'", path);
     }
 }
 
@@ -178,43 +406,93 @@ fn main() {
         .format(|buf, record| writeln!(buf, "[NC] {}: {}", record.level(), record.args()))
         .init();
 
-    info!("Initializing...");
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("record-profile") => {
+            record_profile(&args[2..]);
+            return;
+        }
 
-    info!(
-        "Convert A to Keycode: {}",
-        string_to_keycode(&"A".to_string())
-    );
-    info!("Convert keycode to char: {}", keycode_to_string(38));
+        Some("record") => {
+            record_macro(&args[2..]);
+            return;
+        }
+
+        Some("analyze") => {
+            analyze(&args[2..]);
+            return;
+        }
+
+        _ => {}
+    }
+
+    info!("Initializing...");
+    shutdown::block_signals();
 
     let settings = Settings::load().expect("[NC] Failed to load settings");
     info!("Settings: {:?}", settings);
 
-    let clicker_state = ClickerState::parse(&settings).expect("[NC] Failed to create state");
+    let mut sys = InputSystem::try_init().expect("[NC] Failed to initialize input system");
+    sys.ignore_synthetic = settings.ignore_synthetic;
+    info!("Input devices: {:?}", sys.devices);
+    info!("Synthetic input devices: {:?}", sys.synthetic_devices);
 
     {
-        let mut print_state = vec![];
-        for entry in &clicker_state.commands {
-            print_state.push(entry);
+        let keymap = sys.keymap.read().unwrap();
+        info!("Convert A to Keycode: {:?}", keymap.string_to_keycode("A"));
+        info!("Convert keycode to char: {}", keymap.keycode_to_string(38));
+    }
+    let mut clicker_state = ClickerState::parse(&settings).expect("[NC] Failed to create state");
+    clicker_state.normalize_keys(&sys.keymap.read().unwrap());
+
+    info!("State: {:?}", clicker_state.commands);
+
+    for cmd in &clicker_state.commands {
+        let device = cmd.device.as_deref();
+        for (input, modifiers) in cmd.consumed_inputs() {
+            let grabbed = match input {
+                ClickerInput::Button(b) => sys.grab_button(*b, modifiers, device),
+                ClickerInput::Key(k) => {
+                    let keycode = sys.keymap.read().unwrap().string_to_keycode(k);
+                    let keycode = keycode.expect("[NC] Consumed key isn't on the keyboard");
+                    sys.grab_key(keycode, modifiers, device)
+                }
+            }
+            .expect("[NC] Failed to grab input");
+
+            if grabbed {
+                info!("Consuming {:?}", input);
+            } else {
+                info!(
+                    "Can't consume {:?}, another application grabbed it or the device is missing",
+                    input
+                );
+            }
         }
-        info!("State: {:?}", print_state);
     }
 
-    let sys = Arc::new(InputSystem::try_init().expect("[NC] Failed to initialize input system"));
+    let sys = Arc::new(sys);
     info!("Successfully initialized");
 
-    let state = Arc::new(Mutex::new(clicker_state));
+    let scheduler = Arc::new(Scheduler::new(clicker_state));
+
+    shutdown::set_panic_hook(sys.clone(), scheduler.clone());
+    shutdown::spawn_signal_handler(sys.clone(), scheduler.clone());
+    info!("Installed shutdown handlers");
 
     let clicker_thread = {
         let sys_clone = sys.clone();
-        let state_clone = state.clone();
-        thread::spawn(move || clicker_thread(sys_clone, state_clone, settings.delay))
+        let scheduler_clone = scheduler.clone();
+        thread::spawn(move || clicker_thread(sys_clone, scheduler_clone))
     };
     info!("Started clicker thread");
 
     let event_thread = {
         let sys_clone = sys.clone();
-        let state_clone = state.clone();
-        sys.spawn_event_loop(move |ev| event_handler(ev, sys_clone.clone(), state_clone.clone()))
+        let scheduler_clone = scheduler.clone();
+        sys.spawn_event_loop(move |ev, device| {
+            event_handler(ev, device, sys_clone.clone(), scheduler_clone.clone())
+        })
     };
     info!("Started event loop");
 
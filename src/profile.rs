@@ -0,0 +1,123 @@
+use crate::clicker::ClickerInput;
+use crate::distribution::RangeSampler;
+use crate::inputsys::{InputEvent, InputSystem};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Intervals longer than this are pauses between clicking sessions rather than
+// part of the clicking rhythm, so they are left out of the profile
+const MAX_INTERVAL_MS: f64 = 1000.0;
+
+// Inter-click intervals and hold durations of real clicks, in milliseconds
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Profile {
+    pub intervals: Vec<f64>,
+    pub holds: Vec<f64>,
+}
+
+impl Profile {
+    pub fn load(path: &str) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        serde_json::from_reader(file).ok()
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn cps_sampler(&self) -> Option<RangeSampler> {
+        let cps = self
+            .intervals
+            .iter()
+            .filter(|&&ms| ms > 0.0)
+            .map(|ms| 1000.0 / ms)
+            .collect();
+        RangeSampler::empirical(cps)
+    }
+
+    pub fn hold_sampler(&self) -> Option<RangeSampler> {
+        RangeSampler::empirical(self.holds.clone())
+    }
+}
+
+#[derive(Default)]
+struct Recorder {
+    profile: Profile,
+    last_press: Option<Instant>,
+    pressed_at: Option<Instant>,
+}
+
+impl Recorder {
+    fn press(&mut self, now: Instant) {
+        // Repeated press events without a release in between are ignored
+        if self.pressed_at.is_some() {
+            return;
+        }
+
+        if let Some(last) = self.last_press {
+            let interval = (now - last).as_secs_f64() * 1000.0;
+            if interval <= MAX_INTERVAL_MS {
+                self.profile.intervals.push(interval);
+            }
+        }
+
+        self.last_press = Some(now);
+        self.pressed_at = Some(now);
+    }
+
+    fn release(&mut self, now: Instant) {
+        if let Some(pressed_at) = self.pressed_at.take() {
+            self.profile
+                .holds
+                .push((now - pressed_at).as_secs_f64() * 1000.0);
+        }
+    }
+}
+
+// Captures the presses and releases of 'input' for 'duration'
+pub fn record(sys: &InputSystem, input: ClickerInput, duration: Duration) -> Profile {
+    let recorder = Arc::new(Mutex::new(Recorder::default()));
+    let end = Instant::now() + duration;
+
+    {
+        let recorder = recorder.clone();
+        let keymap = sys.keymap.clone();
+        sys.spawn_event_loop(move |ev, _device| {
+            let now = Instant::now();
+            if now >= end {
+                return false;
+            }
+
+            let keycode_to_string = |key| keymap.read().unwrap().keycode_to_string(key);
+
+            let (ev_input, is_press) = match ev {
+                InputEvent::ButtonPress(btn) => (ClickerInput::Button(btn), true),
+                InputEvent::ButtonRelease(btn) => (ClickerInput::Button(btn), false),
+                InputEvent::KeyPress(key) => (ClickerInput::Key(keycode_to_string(key)), true),
+                InputEvent::KeyRelease(key) => (ClickerInput::Key(keycode_to_string(key)), false),
+                InputEvent::Motion => return true,
+            };
+
+            if ev_input == input {
+                let mut recorder = recorder.lock().unwrap();
+                if is_press {
+                    recorder.press(now);
+                } else {
+                    recorder.release(now);
+                }
+            }
+
+            true
+        });
+    }
+
+    thread::sleep(duration);
+    let mut recorder = recorder.lock().unwrap();
+    std::mem::take(&mut recorder.profile)
+}
@@ -0,0 +1,101 @@
+use crate::settings::{self, RampShape};
+use std::time::Duration;
+
+// Steepness of the exponential shape. Higher values make the ramp cover most of
+// the change right at its beginning
+const EXPONENTIAL_STEEPNESS: f64 = 3.0;
+
+// Blends the CPS of a command from or into a fixed rate over some time
+#[derive(Debug)]
+pub struct Ramp {
+    pub duration: Duration,
+    shape: RampShape,
+    cps: f64,
+}
+
+impl Ramp {
+    pub fn parse(ramp: &settings::Ramp) -> Option<Self> {
+        // NOTE: Written so that NaN fails the check too
+        if !(ramp.cps > 0.0 && ramp.cps.is_finite()) {
+            return None;
+        }
+
+        Some(Self {
+            duration: Duration::from_millis(ramp.duration),
+            shape: ramp.shape.clone(),
+            cps: ramp.cps,
+        })
+    }
+
+    // Goes from the ramp's CPS to 'target' as 'elapsed' approaches the duration
+    pub fn warmup(&self, target: f64, elapsed: Duration) -> f64 {
+        self.cps + (target - self.cps) * self.progress(elapsed)
+    }
+
+    // Goes from 'target' to the ramp's CPS as 'elapsed' approaches the duration
+    pub fn cooldown(&self, target: f64, elapsed: Duration) -> f64 {
+        target + (self.cps - target) * self.progress(elapsed)
+    }
+
+    fn progress(&self, elapsed: Duration) -> f64 {
+        if self.duration.is_zero() {
+            return 1.0;
+        }
+
+        let x = (elapsed.as_secs_f64() / self.duration.as_secs_f64()).min(1.0);
+        match self.shape {
+            RampShape::Linear => x,
+            RampShape::Exponential => {
+                (1.0 - (-EXPONENTIAL_STEEPNESS * x).exp()) / (1.0 - (-EXPONENTIAL_STEEPNESS).exp())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(shape: RampShape, duration: u64, cps: f64) -> Option<Ramp> {
+        Ramp::parse(&settings::Ramp {
+            duration,
+            shape,
+            cps,
+        })
+    }
+
+    #[test]
+    fn rejects_non_positive_and_non_finite_cps() {
+        assert!(ramp(RampShape::Linear, 1000, 4.0).is_some());
+        assert!(ramp(RampShape::Linear, 1000, 0.0).is_none());
+        assert!(ramp(RampShape::Linear, 1000, -4.0).is_none());
+        assert!(ramp(RampShape::Linear, 1000, f64::NAN).is_none());
+        assert!(ramp(RampShape::Linear, 1000, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn ramps_reach_their_ends() {
+        let ms = Duration::from_millis;
+        for shape in [RampShape::Linear, RampShape::Exponential] {
+            let r = ramp(shape, 1000, 4.0).unwrap();
+            assert!((r.warmup(16.0, ms(0)) - 4.0).abs() < 1e-9);
+            assert!((r.warmup(16.0, ms(1000)) - 16.0).abs() < 1e-9);
+            assert!((r.warmup(16.0, ms(5000)) - 16.0).abs() < 1e-9);
+            assert!((r.cooldown(16.0, ms(0)) - 16.0).abs() < 1e-9);
+            assert!((r.cooldown(16.0, ms(1000)) - 4.0).abs() < 1e-9);
+        }
+
+        // Without a duration, the ramp is over right away
+        let instant = ramp(RampShape::Linear, 0, 4.0).unwrap();
+        assert_eq!(instant.warmup(16.0, Duration::ZERO), 16.0);
+    }
+
+    #[test]
+    fn exponential_ramps_change_faster_at_first() {
+        let half = Duration::from_millis(500);
+        let linear = ramp(RampShape::Linear, 1000, 4.0).unwrap();
+        let exponential = ramp(RampShape::Exponential, 1000, 4.0).unwrap();
+        assert!((linear.warmup(16.0, half) - 10.0).abs() < 1e-9);
+        assert!(exponential.warmup(16.0, half) > 10.0);
+    }
+}
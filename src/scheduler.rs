@@ -0,0 +1,68 @@
+use crate::clicker::ClickerState;
+use std::sync::{Condvar, Mutex, MutexGuard, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Sleeping is only accurate to a fraction of a millisecond, so the last stretch
+// before a deadline is spent yielding instead
+const SPIN_THRESHOLD: Duration = Duration::from_micros(1000);
+
+pub struct Scheduler {
+    state: Mutex<ClickerState>,
+    wakeup: Condvar,
+}
+
+impl Scheduler {
+    pub fn new(state: ClickerState) -> Self {
+        Self {
+            state: Mutex::new(state),
+            wakeup: Condvar::new(),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, ClickerState> {
+        self.state.lock().unwrap()
+    }
+
+    // Same as 'lock', but gives up instead of waiting, and doesn't mind a
+    // thread that panicked while holding the state
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, ClickerState>> {
+        match self.state.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    // Wakes up the clicker thread so that it picks up state changes
+    pub fn notify(&self) {
+        self.wakeup.notify_all();
+    }
+
+    // Releases the state until 'deadline' is reached or 'notify' is called.
+    // Without a deadline, only 'notify' wakes the caller up
+    pub fn wait_until<'a>(
+        &'a self,
+        guard: MutexGuard<'a, ClickerState>,
+        deadline: Option<Instant>,
+    ) -> MutexGuard<'a, ClickerState> {
+        let Some(deadline) = deadline else {
+            return self.wakeup.wait(guard).unwrap();
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining > SPIN_THRESHOLD {
+            let (guard, _) = self
+                .wakeup
+                .wait_timeout(guard, remaining - SPIN_THRESHOLD)
+                .unwrap();
+            return guard;
+        }
+
+        drop(guard);
+        while Instant::now() < deadline {
+            thread::yield_now();
+        }
+        self.lock()
+    }
+}
@@ -1,12 +1,100 @@
 use config::{Config, ConfigError, File};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Debug)]
 pub struct Command {
-    pub action: Input,
-    pub listen: Input,
+    pub action: Action,
+    pub listen: Listen,
+    pub device: Option<String>,
+    #[serde(default)]
+    pub consume: bool,
     pub method: Method,
     pub range: Option<CpsRange>,
+    pub distribution: Option<Distribution>,
+    pub hold: Option<MillisRange>,
+    pub hold_distribution: Option<Distribution>,
+    pub fatigue: Option<Fatigue>,
+    pub profile: Option<String>,
+    pub model: Option<Model>,
+    pub seed: Option<u64>,
+    pub warmup: Option<Ramp>,
+    pub cooldown: Option<Ramp>,
+    pub stop_after: Option<u64>,
+    pub artifacts: Option<Artifacts>,
+    pub jitter: Option<Jitter>,
+    pub position: Option<Position>,
+    #[serde(default)]
+    pub restore_pointer: bool,
+    pub pointer_motion: Option<PointerMotion>,
+    pub window: Option<Window>,
+}
+
+// Either a single input to press or click, or a macro
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Action {
+    Input(Input),
+    Macro(Macro),
+}
+
+// The steps of a macro, either inline or from a JSON file
+#[derive(Deserialize, Debug)]
+pub struct Macro {
+    pub steps: Option<Vec<Step>>,
+    pub file: Option<String>,
+    // Waits are divided by it, so 2 runs the macro twice as fast
+    pub speed: Option<f64>,
+    // Standard deviation of a random factor applied to every wait
+    pub humanize: Option<f64>,
+}
+
+// NOTE: Also written by the 'record' subcommand, which leaves out the fields
+// that aren't set
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Step {
+    pub r#type: StepType,
+    // The key or button of the step
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    // Fixed wait, in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+    // Random wait, in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+    // Pointer movement, in pixels. For button steps, the position on the screen
+    // to move to before pressing or releasing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<i16>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum StepType {
+    KeyDown,
+    KeyUp,
+    KeyClick,
+    ButtonDown,
+    ButtonUp,
+    ButtonClick,
+    Wait,
+    // Moves the pointer instantly, relative to its position
+    Move,
+    // Moves the pointer along a human-like path, to a position on the screen
+    // or relative to its position
+    MoveTo,
+    MoveBy,
+}
+
+// Either a single input or a chord of inputs that are held together
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Listen {
+    Input(Input),
+    Chord(Vec<Input>),
 }
 
 #[derive(Deserialize, Debug)]
@@ -15,7 +103,7 @@ pub struct Input {
     pub value: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub enum InputType {
     Key,
     Button,
@@ -25,18 +113,141 @@ pub enum InputType {
 pub enum Method {
     Hold,
     Toggle,
+    Once,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct CpsRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MillisRange {
     pub min: u32,
     pub max: u32,
 }
 
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum DistributionType {
+    Uniform,
+    Normal,
+    LogNormal,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Distribution {
+    pub r#type: DistributionType,
+    pub mean: Option<f64>,
+    pub stddev: Option<f64>,
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Fatigue {
+    pub rate: f64,
+    pub floor: f64,
+    pub recovery: f64,
+    #[serde(default)]
+    pub wander: f64,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ModelType {
+    AR1,
+    Markov,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Model {
+    pub r#type: ModelType,
+    pub phi: Option<f64>,
+    pub transitions: Option<Vec<Vec<f64>>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RampShape {
+    Linear,
+    Exponential,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Ramp {
+    pub duration: u64,
+    pub shape: RampShape,
+    pub cps: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Artifacts {
+    #[serde(default)]
+    pub miss: f64,
+    #[serde(default)]
+    pub double: f64,
+    #[serde(default)]
+    pub pause: f64,
+    pub double_delay: Option<MillisRange>,
+    pub pause_duration: Option<MillisRange>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Jitter {
+    pub amplitude: f64,
+    pub frequency: f64,
+    #[serde(default)]
+    pub drift: f64,
+}
+
+// Where a command clicks, either one target or a list of targets that the
+// clicks cycle through
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Position {
+    Target(Target),
+    Cycle(Vec<Target>),
+}
+
+// How the pointer gets to the position of a click and back
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerMotion {
+    Jump,
+    // Along a human-like path, like the MoveTo step of a macro
+    Path,
+}
+
+// A point on the screen, optionally grown into a circle or a rectangle
+#[derive(Deserialize, Debug)]
+pub struct Target {
+    pub x: i16,
+    pub y: i16,
+    pub radius: Option<f64>,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+}
+
+// The window that the positions of a command are relative to, matched by
+// exactly one of these
+#[derive(Deserialize, Debug)]
+pub struct Window {
+    // Instance or class name
+    pub class: Option<String>,
+    // Regular expression
+    pub title: Option<String>,
+    #[serde(default)]
+    pub active: bool,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Settings {
     pub commands: Vec<Command>,
-    pub delay: u64,
+    pub seed: Option<u64>,
+    #[serde(default = "default_ignore_synthetic")]
+    pub ignore_synthetic: bool,
+}
+
+fn default_ignore_synthetic() -> bool {
+    true
 }
 
 impl Settings {
@@ -47,4 +258,13 @@ impl Settings {
 
         cfg.try_deserialize::<Settings>()
     }
+
+    #[cfg(test)]
+    pub fn from_toml(toml: &str) -> Result<Self, ConfigError> {
+        let cfg = Config::builder()
+            .add_source(File::from_str(toml, config::FileFormat::Toml))
+            .build()?;
+
+        cfg.try_deserialize::<Settings>()
+    }
 }
@@ -0,0 +1,76 @@
+use crate::fakekeyboard;
+use crate::fakemouse;
+use crate::inputsys::{HeldInput, InputSystem};
+use crate::scheduler::Scheduler;
+use log::info;
+use std::sync::Arc;
+use std::{mem, panic, process, ptr, thread};
+
+// Releases every key and button that is faked as held down, so that none of
+// them stays stuck after the process exits
+fn release_held(sys: &InputSystem) {
+    for input in sys.take_held() {
+        info!("Releasing {:?}", input);
+        // Errors are ignored, the process is exiting anyway
+        let _ = match input {
+            HeldInput::Button(b) => fakemouse::release(sys, &b),
+            HeldInput::Key(k) => fakekeyboard::release(sys, k),
+        };
+    }
+}
+
+fn signal_set() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        set
+    }
+}
+
+// Blocks SIGINT and SIGTERM in the calling thread and in the threads it spawns
+// afterwards, so that they are only received by 'spawn_signal_handler'.
+// NOTE: Has to be called before spawning any other thread
+pub fn block_signals() {
+    let set = signal_set();
+    unsafe {
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+    }
+}
+
+// Waits for SIGINT or SIGTERM, then stops clicking and exits cleanly
+pub fn spawn_signal_handler(
+    sys: Arc<InputSystem>,
+    scheduler: Arc<Scheduler>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let set = signal_set();
+        let mut signal = 0;
+        unsafe {
+            libc::sigwait(&set, &mut signal);
+        }
+        info!("Received signal {}, shutting down", signal);
+
+        // Holding the state keeps the clicker thread from pressing anything
+        // else until the process exits
+        let _state = scheduler.lock();
+        release_held(&sys);
+        process::exit(0);
+    })
+}
+
+// Releases the held inputs and exits when any thread panics, instead of
+// leaving the other threads running
+pub fn set_panic_hook(sys: Arc<InputSystem>, scheduler: Arc<Scheduler>) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        // NOTE: The state can't be taken if the panicking thread holds it, in
+        // which case the clicker thread isn't running anyway
+        let _state = scheduler.try_lock();
+        release_held(&sys);
+        process::exit(101);
+    }));
+}
@@ -0,0 +1,276 @@
+use crate::fakemouse;
+use crate::inputsys::InputSystem;
+use crate::settings::{self, PointerMotion};
+use crate::trajectory::Trajectory;
+use crate::window::WindowTarget;
+use rand::Rng;
+use std::f64::consts::TAU;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+enum Area {
+    Point,
+    Circle(f64),
+    // Width and height, from the point towards the bottom right
+    Rectangle(u16, u16),
+}
+
+#[derive(Debug)]
+struct Target {
+    x: i16,
+    y: i16,
+    area: Area,
+}
+
+impl Target {
+    fn parse(target: &settings::Target) -> Option<Self> {
+        let area = match (target.radius, target.width, target.height) {
+            (None, None, None) => Area::Point,
+            (Some(r), None, None) if r >= 0.0 => Area::Circle(r),
+            (None, Some(w), Some(h)) => Area::Rectangle(w, h),
+            _ => return None,
+        };
+
+        Some(Self {
+            x: target.x,
+            y: target.y,
+            area,
+        })
+    }
+
+    // A random point inside the target, spread evenly over its area
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> (i16, i16) {
+        let (dx, dy) = match self.area {
+            Area::Point => (0.0, 0.0),
+            Area::Circle(radius) => {
+                // NOTE: The square root keeps the points from bunching up in
+                // the center
+                let distance = radius * rng.gen::<f64>().sqrt();
+                let angle = rng.gen_range(0.0..TAU);
+                (distance * angle.cos(), distance * angle.sin())
+            }
+            Area::Rectangle(w, h) => (rng.gen_range(0..=w) as f64, rng.gen_range(0..=h) as f64),
+        };
+
+        (
+            (self.x as f64 + dx).round() as i16,
+            (self.y as f64 + dy).round() as i16,
+        )
+    }
+}
+
+// Where the pointer is when a click is due
+pub enum Aim {
+    // The window of the targets isn't mapped, the click is skipped
+    Unmapped,
+    // The pointer is on its way, the click is due once it got there
+    Moving(Duration),
+    OnTarget,
+}
+
+// The places on the screen that a command clicks at, one after the other
+#[derive(Debug)]
+pub struct Targets {
+    targets: Vec<Target>,
+    next: usize,
+    restore_pointer: bool,
+    // Whether the pointer follows a path to the targets instead of jumping
+    is_path: bool,
+    // Where the pointer was before the click that is in progress, kept until
+    // it's back there
+    saved: Option<(i16, i16)>,
+    // Whether the pointer is following a path towards the target of the click
+    // that is due
+    is_aiming: bool,
+    // The path the pointer is following, towards a target or back
+    trajectory: Trajectory,
+    // When the pointer moves to the next point of the path
+    pub next_move: Option<Instant>,
+}
+
+impl Targets {
+    pub fn parse(
+        position: &settings::Position,
+        restore_pointer: bool,
+        motion: Option<PointerMotion>,
+    ) -> Option<Self> {
+        let targets = match position {
+            settings::Position::Target(t) => vec![Target::parse(t)?],
+            settings::Position::Cycle(ts) => ts.iter().map(Target::parse).collect::<Option<_>>()?,
+        };
+
+        if targets.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            targets,
+            next: 0,
+            restore_pointer,
+            is_path: motion == Some(PointerMotion::Path),
+            saved: None,
+            is_aiming: false,
+            trajectory: Trajectory::default(),
+            next_move: None,
+        })
+    }
+
+    // Gets the pointer to a random point of the next target, remembering where
+    // it was if it has to be restored. The targets are relative to 'window' if
+    // there is one. Following a path takes a while, after which this has to be
+    // called again for the click
+    pub fn aim<R: Rng + ?Sized>(
+        &mut self,
+        sys: &InputSystem,
+        rng: &mut R,
+        window: Option<&mut WindowTarget>,
+        now: Instant,
+    ) -> xcb::Result<Aim> {
+        if self.is_aiming {
+            self.is_aiming = false;
+            return Ok(Aim::OnTarget);
+        }
+
+        let (origin_x, origin_y) = match window {
+            Some(w) => match w.origin(sys)? {
+                Some(origin) => origin,
+                None => return Ok(Aim::Unmapped),
+            },
+            None => (0, 0),
+        };
+
+        let from = sys.pointer_position()?;
+        // NOTE: While the pointer is still on its way back, it's saved where
+        // it's going rather than where it is
+        if self.restore_pointer && self.saved.is_none() {
+            self.saved = Some(from);
+        }
+
+        let (x, y) = self.targets[self.next].sample(rng);
+        self.next = (self.next + 1) % self.targets.len();
+        let to = (origin_x.saturating_add(x), origin_y.saturating_add(y));
+
+        if !self.is_path {
+            self.stop_moving();
+            fakemouse::move_absolute(sys, to.0, to.1)?;
+            return Ok(Aim::OnTarget);
+        }
+
+        self.trajectory = Trajectory::generate(from, to, rng);
+        self.next_move = Some(now);
+        self.is_aiming = true;
+        Ok(Aim::Moving(self.trajectory.duration()))
+    }
+
+    // Moves the pointer back to where it was before 'aim', once the click is over
+    pub fn restore<R: Rng + ?Sized>(
+        &mut self,
+        sys: &InputSystem,
+        rng: &mut R,
+        now: Instant,
+    ) -> xcb::Result<()> {
+        let Some((x, y)) = self.saved else {
+            return Ok(());
+        };
+
+        if !self.is_path {
+            self.saved = None;
+            self.stop_moving();
+            return fakemouse::move_absolute(sys, x, y);
+        }
+
+        self.trajectory = Trajectory::generate(sys.pointer_position()?, (x, y), rng);
+        self.next_move = Some(now);
+        Ok(())
+    }
+
+    // Gives up on the click the pointer is on its way to, e.g. because the
+    // command was deactivated meanwhile
+    pub fn cancel<R: Rng + ?Sized>(
+        &mut self,
+        sys: &InputSystem,
+        rng: &mut R,
+        now: Instant,
+    ) -> xcb::Result<()> {
+        if !self.is_aiming {
+            return Ok(());
+        }
+
+        self.is_aiming = false;
+        self.stop_moving();
+        self.restore(sys, rng, now)
+    }
+
+    // Moves the pointer along its path, up to where it should be at 'now'
+    pub fn follow(&mut self, sys: &InputSystem, now: Instant) -> xcb::Result<()> {
+        while let Some(at) = self.next_move.filter(|&at| at <= now) {
+            // The next point is due after the previous one rather than after
+            // 'now', so that wakeup latency doesn't slow the pointer down
+            match self.trajectory.advance(sys)? {
+                Some(wait) => self.next_move = Some(at + wait),
+                None => {
+                    self.next_move = None;
+                    // The pointer is back where it was
+                    if !self.is_aiming {
+                        self.saved = None;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn stop_moving(&mut self) {
+        self.trajectory.clear();
+        self.next_move = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn target(radius: Option<f64>, size: Option<(u16, u16)>) -> settings::Target {
+        settings::Target {
+            x: 100,
+            y: 200,
+            radius,
+            width: size.map(|(w, _)| w),
+            height: size.map(|(_, h)| h),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_areas() {
+        assert!(Target::parse(&target(None, None)).is_some());
+        assert!(Target::parse(&target(Some(-1.0), None)).is_none());
+        assert!(Target::parse(&target(Some(5.0), Some((10, 10)))).is_none());
+
+        let cycle = settings::Position::Cycle(vec![]);
+        assert!(Targets::parse(&cycle, false, None).is_none());
+    }
+
+    #[test]
+    fn samples_stay_within_the_area() {
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let point = Target::parse(&target(None, None)).unwrap();
+        assert_eq!(point.sample(&mut rng), (100, 200));
+
+        let circle = Target::parse(&target(Some(6.0), None)).unwrap();
+        for _ in 0..1000 {
+            let (x, y) = circle.sample(&mut rng);
+            let distance = ((x - 100) as f64).hypot((y - 200) as f64);
+            // Rounding to whole pixels can go a bit past the radius
+            assert!(distance <= 6.0 + 1.0, "{} is outside the circle", distance);
+        }
+
+        let rectangle = Target::parse(&target(None, Some((30, 10)))).unwrap();
+        for _ in 0..1000 {
+            let (x, y) = rectangle.sample(&mut rng);
+            assert!((100..=130).contains(&x) && (200..=210).contains(&y));
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use chrono::Utc;
+use std::time::Duration;
 
-pub fn get_timestamp() -> i64 {
-    Utc::now().timestamp_millis()
+pub fn cps_to_interval(cps: f64) -> Duration {
+    Duration::from_secs_f64(1.0 / cps)
 }
 
-pub fn cps_to_millis(cps: u32) -> i64 {
-    return ((1.0 / cps as f64) * 1000.0) as i64;
+pub fn millis_to_duration(millis: f64) -> Duration {
+    Duration::from_secs_f64(millis / 1000.0)
 }
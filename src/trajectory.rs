@@ -0,0 +1,194 @@
+use crate::fakemouse;
+use crate::inputsys::InputSystem;
+use crate::time::millis_to_duration;
+use rand::Rng;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// How often the pointer is moved while following a path
+const STEP_INTERVAL_MS: f64 = 8.0;
+
+// Moves at least this long sometimes go a bit past the target and come back
+const OVERSHOOT_MIN_DISTANCE: f64 = 80.0;
+const OVERSHOOT_CHANCE: f64 = 0.3;
+const OVERSHOOT_MAX: f64 = 15.0;
+
+type Point = (f64, f64);
+
+// A human-like path of the pointer, followed one point at a time so that
+// whoever moves the pointer can wait between the points without blocking
+#[derive(Debug, Default)]
+pub struct Trajectory {
+    // Every point with how long to stay there before moving to the next one
+    points: VecDeque<((i16, i16), Duration)>,
+}
+
+impl Trajectory {
+    // A curved path from 'from' to 'to', walked along with a minimum-jerk speed
+    // profile, slow at both ends and fast in the middle. Longer moves take
+    // longer, and their speed varies from one move to the next
+    pub fn generate<R: Rng + ?Sized>(from: (i16, i16), to: (i16, i16), rng: &mut R) -> Self {
+        let mut trajectory = Self::default();
+        let from = (from.0 as f64, from.1 as f64);
+        let to = (to.0 as f64, to.1 as f64);
+        let distance = (to.0 - from.0).hypot(to.1 - from.1);
+        if distance == 0.0 {
+            return trajectory;
+        }
+
+        if distance >= OVERSHOOT_MIN_DISTANCE && rng.gen_bool(OVERSHOOT_CHANCE) {
+            let (dir_x, dir_y) = ((to.0 - from.0) / distance, (to.1 - from.1) / distance);
+            let past = (distance * rng.gen_range(0.02..=0.06)).min(OVERSHOOT_MAX);
+            let aside = past * rng.gen_range(-0.5..=0.5);
+            let overshoot = (
+                to.0 + dir_x * past - dir_y * aside,
+                to.1 + dir_y * past + dir_x * aside,
+            );
+            trajectory.add_segment(from, overshoot, rng);
+            trajectory.add_segment(overshoot, to, rng);
+        } else {
+            trajectory.add_segment(from, to, rng);
+        }
+
+        // Nothing to wait for after reaching the target
+        if let Some((_, wait)) = trajectory.points.back_mut() {
+            *wait = Duration::ZERO;
+        }
+        trajectory
+    }
+
+    fn add_segment<R: Rng + ?Sized>(&mut self, from: Point, to: Point, rng: &mut R) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let distance = dx.hypot(dy);
+        if distance == 0.0 {
+            return;
+        }
+
+        // NOTE: Roughly Fitts's law, the duration grows with the logarithm of
+        // the distance
+        let duration_ms =
+            (100.0 + 100.0 * (1.0 + distance / 10.0).log2()) * rng.gen_range(0.8..=1.2);
+
+        // The control points are pushed to the sides of the straight line by
+        // up to a fifth of its length, which bends the path
+        let (normal_x, normal_y) = (-dy / distance, dx / distance);
+        let bend_1 = distance * rng.gen_range(-0.2..=0.2);
+        let bend_2 = distance * rng.gen_range(-0.2..=0.2);
+        let control_1 = (
+            from.0 + dx / 3.0 + normal_x * bend_1,
+            from.1 + dy / 3.0 + normal_y * bend_1,
+        );
+        let control_2 = (
+            from.0 + dx * 2.0 / 3.0 + normal_x * bend_2,
+            from.1 + dy * 2.0 / 3.0 + normal_y * bend_2,
+        );
+
+        let steps = (duration_ms / STEP_INTERVAL_MS).ceil().max(1.0) as usize;
+        let wait = millis_to_duration(duration_ms / steps as f64);
+        for i in 1..=steps {
+            let t = min_jerk(i as f64 / steps as f64);
+            let (x, y) = bezier(from, control_1, control_2, to, t);
+            let point = (x.round() as i16, y.round() as i16);
+
+            // A point that rounds to the previous one just stays there longer
+            match self.points.back_mut() {
+                Some((last, last_wait)) if *last == point => *last_wait += wait,
+                _ => self.points.push_back((point, wait)),
+            }
+        }
+    }
+
+    // Moves the pointer to the next point and returns how long to stay there,
+    // or None once the path was followed to its end
+    pub fn advance(&mut self, sys: &InputSystem) -> xcb::Result<Option<Duration>> {
+        let Some(((x, y), wait)) = self.points.pop_front() else {
+            return Ok(None);
+        };
+
+        fakemouse::move_absolute(sys, x, y)?;
+        Ok(Some(wait))
+    }
+
+    // How long it takes to follow the rest of the path
+    pub fn duration(&self) -> Duration {
+        self.points.iter().map(|(_, wait)| *wait).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+}
+
+// The share of the distance covered after the share 't' of the duration
+fn min_jerk(t: f64) -> f64 {
+    t * t * t * (10.0 - 15.0 * t + 6.0 * t * t)
+}
+
+fn bezier(p0: Point, p1: Point, p2: Point, p3: Point, t: f64) -> Point {
+    let u = 1.0 - t;
+    let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+    (
+        a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+        a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn paths_end_on_the_target() {
+        let mut rng = StdRng::seed_from_u64(4);
+        for to in [(1, 0), (40, -30), (900, 500), (-600, 20)] {
+            let trajectory = Trajectory::generate((100, 100), to, &mut rng);
+            let (last, wait) = *trajectory.points.back().unwrap();
+            assert_eq!(last, to);
+            assert_eq!(wait, Duration::ZERO);
+
+            // Only the last point has nothing to wait for, and the pointer
+            // moves at every point
+            let points: Vec<_> = trajectory.points.iter().collect();
+            for pair in points.windows(2) {
+                assert!(!pair[0].1.is_zero());
+                assert_ne!(pair[0].0, pair[1].0);
+            }
+        }
+    }
+
+    #[test]
+    fn no_path_to_where_the_pointer_is() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let trajectory = Trajectory::generate((30, 30), (30, 30), &mut rng);
+        assert!(trajectory.points.is_empty());
+        assert_eq!(trajectory.duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn longer_moves_take_longer() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut average = |distance: i16| {
+            let total: Duration = (0..200)
+                .map(|_| Trajectory::generate((0, 0), (distance, 0), &mut rng).duration())
+                .sum();
+            total / 200
+        };
+        let (short, long) = (average(20), average(1000));
+        assert!(short < long);
+
+        // Roughly Fitts's law, with the spread of the speed and any overshoot
+        let fitts = |distance: f64| 100.0 + 100.0 * (1.0 + distance / 10.0).log2();
+        assert!((short.as_secs_f64() * 1000.0) > 0.8 * fitts(20.0));
+        assert!((long.as_secs_f64() * 1000.0) < 1.2 * 2.0 * fitts(1000.0));
+    }
+
+    #[test]
+    fn min_jerk_goes_from_start_to_end() {
+        assert_eq!(min_jerk(0.0), 0.0);
+        assert_eq!(min_jerk(1.0), 1.0);
+        assert!((min_jerk(0.5) - 0.5).abs() < 1e-12);
+        assert!(min_jerk(0.1) < 0.1);
+    }
+}
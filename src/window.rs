@@ -0,0 +1,284 @@
+use crate::inputsys::InputSystem;
+use crate::settings;
+use log::info;
+use regex::Regex;
+use std::time::{Duration, Instant};
+use xcb::{x, Connection};
+
+// How long the windows aren't searched again after a search found nothing.
+// Walking the whole tree takes several requests for every window
+const SEARCH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+enum Matcher {
+    // Either the instance or the class name of WM_CLASS
+    Class(String),
+    Title(Regex),
+    // Whichever window has the focus
+    Active,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Atoms {
+    net_active_window: x::Atom,
+    net_wm_name: x::Atom,
+}
+
+impl Atoms {
+    fn intern(conn: &Connection) -> xcb::Result<Self> {
+        let intern = |name: &[u8]| {
+            conn.send_request(&x::InternAtom {
+                only_if_exists: false,
+                name,
+            })
+        };
+        let net_active_window = intern(b"_NET_ACTIVE_WINDOW");
+        let net_wm_name = intern(b"_NET_WM_NAME");
+
+        Ok(Self {
+            net_active_window: conn.wait_for_reply(net_active_window)?.atom(),
+            net_wm_name: conn.wait_for_reply(net_wm_name)?.atom(),
+        })
+    }
+}
+
+// The window that the positions of a command are relative to
+#[derive(Debug)]
+pub struct WindowTarget {
+    matcher: Matcher,
+    // The window found last time, checked again before searching the others
+    found: Option<x::Window>,
+    // When the last search found nothing
+    missed_at: Option<Instant>,
+    // Interned on first use, so that parsing doesn't need the X server
+    atoms: Option<Atoms>,
+    is_mapped: bool,
+}
+
+impl WindowTarget {
+    pub fn parse(window: &settings::Window) -> Option<Self> {
+        let matcher = match (&window.class, &window.title, window.active) {
+            (Some(class), None, false) => Matcher::Class(class.clone()),
+            (None, Some(title), false) => Matcher::Title(Regex::new(title).ok()?),
+            (None, None, true) => Matcher::Active,
+            _ => return None,
+        };
+
+        Some(Self {
+            matcher,
+            found: None,
+            missed_at: None,
+            atoms: None,
+            is_mapped: true,
+        })
+    }
+
+    // The position on the screen of the top left corner of the window, or None
+    // if no matching window is mapped
+    pub fn origin(&mut self, sys: &InputSystem) -> xcb::Result<Option<(i16, i16)>> {
+        let window = self.find(sys)?;
+
+        let origin = match window {
+            Some(window) => reply(
+                &sys.conn,
+                sys.conn.send_request(&x::TranslateCoordinates {
+                    src_window: window,
+                    dst_window: sys.window,
+                    src_x: 0,
+                    src_y: 0,
+                }),
+            )?
+            .map(|r| (r.dst_x(), r.dst_y())),
+            None => None,
+        };
+
+        if origin.is_some() != self.is_mapped {
+            self.is_mapped = origin.is_some();
+            if self.is_mapped {
+                info!("Found target window {:?}", self.matcher);
+            } else {
+                info!("Target window {:?} isn't mapped, pausing", self.matcher);
+            }
+        }
+
+        Ok(origin)
+    }
+
+    fn find(&mut self, sys: &InputSystem) -> xcb::Result<Option<x::Window>> {
+        let atoms = match self.atoms {
+            Some(atoms) => atoms,
+            None => *self.atoms.insert(Atoms::intern(&sys.conn)?),
+        };
+
+        // NOTE: The active window changes all the time, so it isn't cached
+        if let Matcher::Active = self.matcher {
+            let active = get_property(&sys.conn, sys.window, atoms.net_active_window)?
+                .filter(|r| r.format() == 32)
+                .and_then(|r| r.value::<x::Window>().first().copied());
+            return match active {
+                Some(window) if is_viewable(&sys.conn, window)? => Ok(Some(window)),
+                _ => Ok(None),
+            };
+        }
+
+        if let Some(window) = self.found {
+            if is_viewable(&sys.conn, window)? && self.matches(&sys.conn, window, atoms)? {
+                return Ok(Some(window));
+            }
+        }
+
+        let now = Instant::now();
+        if !self.is_search_due(now) {
+            return Ok(None);
+        }
+
+        self.found = self.search(&sys.conn, sys.window, atoms)?;
+        self.missed_at = self.found.is_none().then_some(now);
+        Ok(self.found)
+    }
+
+    fn is_search_due(&self, now: Instant) -> bool {
+        self.missed_at
+            .is_none_or(|missed_at| now - missed_at >= SEARCH_INTERVAL)
+    }
+
+    // Looks for a mapped window matching among the descendants of 'parent',
+    // the topmost ones first
+    fn search(
+        &self,
+        conn: &Connection,
+        parent: x::Window,
+        atoms: Atoms,
+    ) -> xcb::Result<Option<x::Window>> {
+        let Some(tree) = reply(conn, conn.send_request(&x::QueryTree { window: parent }))? else {
+            return Ok(None);
+        };
+
+        for &window in tree.children().iter().rev() {
+            // The children of an unmapped window aren't visible either
+            if !is_viewable(conn, window)? {
+                continue;
+            }
+
+            if self.matches(conn, window, atoms)? {
+                return Ok(Some(window));
+            }
+
+            if let Some(found) = self.search(conn, window, atoms)? {
+                return Ok(Some(found));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn matches(&self, conn: &Connection, window: x::Window, atoms: Atoms) -> xcb::Result<bool> {
+        match &self.matcher {
+            Matcher::Class(class) => {
+                // NOTE: WM_CLASS holds the instance and the class name, each
+                // followed by a null byte
+                let Some(wm_class) = get_string(conn, window, x::ATOM_WM_CLASS)? else {
+                    return Ok(false);
+                };
+                Ok(wm_class.split('\0').any(|name| name == class))
+            }
+
+            Matcher::Title(regex) => {
+                let title = match get_string(conn, window, atoms.net_wm_name)? {
+                    Some(title) => Some(title),
+                    None => get_string(conn, window, x::ATOM_WM_NAME)?,
+                };
+                Ok(title.is_some_and(|title| regex.is_match(&title)))
+            }
+
+            Matcher::Active => Ok(false),
+        }
+    }
+}
+
+// Windows can be destroyed at any time, which makes the requests about them
+// fail. Those failures are the same as the window not being there
+fn reply<C: xcb::CookieWithReplyChecked>(
+    conn: &Connection,
+    cookie: C,
+) -> xcb::Result<Option<C::Reply>> {
+    match conn.wait_for_reply(cookie) {
+        Ok(r) => Ok(Some(r)),
+        Err(xcb::Error::Protocol(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_viewable(conn: &Connection, window: x::Window) -> xcb::Result<bool> {
+    let attributes = reply(conn, conn.send_request(&x::GetWindowAttributes { window }))?;
+    Ok(attributes.is_some_and(|a| a.map_state() == x::MapState::Viewable))
+}
+
+fn get_property(
+    conn: &Connection,
+    window: x::Window,
+    property: x::Atom,
+) -> xcb::Result<Option<x::GetPropertyReply>> {
+    reply(
+        conn,
+        conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property,
+            r#type: x::ATOM_ANY,
+            long_offset: 0,
+            long_length: 1024,
+        }),
+    )
+}
+
+fn get_string(
+    conn: &Connection,
+    window: x::Window,
+    property: x::Atom,
+) -> xcb::Result<Option<String>> {
+    let string = get_property(conn, window, property)?
+        .filter(|r| r.format() == 8 && !r.value::<u8>().is_empty())
+        .map(|r| String::from_utf8_lossy(r.value::<u8>()).into_owned());
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(class: Option<&str>, title: Option<&str>, active: bool) -> settings::Window {
+        settings::Window {
+            class: class.map(str::to_string),
+            title: title.map(str::to_string),
+            active,
+        }
+    }
+
+    #[test]
+    fn needs_exactly_one_matcher() {
+        assert!(WindowTarget::parse(&window(Some("firefox"), None, false)).is_some());
+        assert!(WindowTarget::parse(&window(None, Some("^Minecraft"), false)).is_some());
+        assert!(WindowTarget::parse(&window(None, None, true)).is_some());
+
+        assert!(WindowTarget::parse(&window(None, None, false)).is_none());
+        assert!(WindowTarget::parse(&window(Some("firefox"), None, true)).is_none());
+        assert!(WindowTarget::parse(&window(Some("firefox"), Some("Mozilla"), false)).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_title_patterns() {
+        assert!(WindowTarget::parse(&window(None, Some("(unclosed"), false)).is_none());
+    }
+
+    #[test]
+    fn failed_searches_are_throttled() {
+        let mut target = WindowTarget::parse(&window(Some("firefox"), None, false)).unwrap();
+        let now = Instant::now();
+        assert!(target.is_search_due(now));
+
+        target.missed_at = Some(now);
+        assert!(!target.is_search_due(now + SEARCH_INTERVAL / 2));
+        assert!(target.is_search_due(now + SEARCH_INTERVAL));
+    }
+}